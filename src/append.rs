@@ -0,0 +1,152 @@
+use std::{
+    cmp::Ordering,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::cli::ConcatOrder;
+
+/// Append `markdown` to `target` under a heading naming the date and `source`,
+/// creating the file if needed. The file is locked while writing so concurrent
+/// jobs, or processes, never interleave their sections.
+pub fn append_section(target: &Path, source: &Path, markdown: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(target)?;
+    file.lock()?;
+
+    let date = chrono::Local::now().format("%Y-%m-%d %H:%M");
+    let section = format!(
+        "\n## {date} — {}\n\n{}\n",
+        source.display(),
+        markdown.trim_end()
+    );
+
+    // the lock is released when the file is closed
+    file.write_all(section.as_bytes())
+}
+
+/// Append all `(source, markdown)` sections to `target`, sorted by `order`.
+pub fn append_sorted(
+    target: &Path,
+    mut sections: Vec<(PathBuf, String)>,
+    order: ConcatOrder,
+) -> std::io::Result<()> {
+    match order {
+        ConcatOrder::Path => sections.sort_by(|(a, _), (b, _)| a.cmp(b)),
+        // notes whose mtime can't be read sort first, in path order
+        ConcatOrder::Mtime => sections.sort_by_cached_key(|(source, _)| {
+            let modified = std::fs::metadata(source).and_then(|meta| meta.modified());
+            (
+                modified.ok().unwrap_or(SystemTime::UNIX_EPOCH),
+                source.clone(),
+            )
+        }),
+        ConcatOrder::Numeric => sections
+            .sort_by(|(a, _), (b, _)| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy())),
+    }
+
+    for (source, markdown) in sections {
+        append_section(target, &source, &markdown)?;
+    }
+
+    Ok(())
+}
+
+/// All `(source, markdown)` sections as one document, sorted by source path and
+/// headed by the source file names
+pub fn merge(mut sections: Vec<(PathBuf, String)>) -> String {
+    sections.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    sections
+        .iter()
+        .map(|(source, markdown)| {
+            let name = source.file_name().unwrap_or(source.as_os_str());
+            format!("## {}\n\n{}\n", name.to_string_lossy(), markdown.trim_end())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Compare `a` and `b` character by character, except that runs of digits are
+/// compared by their value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (run_a, rest_a) = a.split_at(digits(a));
+            let (run_b, rest_b) = b.split_at(digits(b));
+
+            // leading zeros don't change the value, only break ties
+            let (trim_a, trim_b) = (run_a.trim_start_matches('0'), run_b.trim_start_matches('0'));
+            let ordering = trim_a
+                .len()
+                .cmp(&trim_b.len())
+                .then_with(|| trim_a.cmp(trim_b))
+                .then_with(|| run_a.len().cmp(&run_b.len()));
+
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (rest_a, rest_b);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digit_runs_compare_by_value() {
+        assert_eq!(natural_cmp("day2", "day10"), Ordering::Less);
+        assert_eq!(natural_cmp("day10", "day2"), Ordering::Greater);
+        assert_eq!(natural_cmp("a2b", "a10a"), Ordering::Less);
+    }
+
+    #[test]
+    fn other_characters_compare_as_is() {
+        assert_eq!(natural_cmp("day10", "day10"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "b"), Ordering::Less);
+        assert_eq!(natural_cmp("é2", "é10"), Ordering::Less);
+    }
+
+    #[test]
+    fn prefix_sorts_first() {
+        assert_eq!(natural_cmp("day", "day1"), Ordering::Less);
+        assert_eq!(natural_cmp("day1", "day1b"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("07", "7"), Ordering::Greater);
+        assert_eq!(natural_cmp("007", "10"), Ordering::Less);
+    }
+
+    #[test]
+    fn runs_longer_than_any_integer_still_compare() {
+        assert_eq!(
+            natural_cmp("note123456789012345678901234567890", "note99"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn sorts_lectures_in_order() {
+        let mut names = ["lecture10", "lecture2", "lecture1", "lecture02"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+
+        assert_eq!(names, ["lecture1", "lecture2", "lecture02", "lecture10"]);
+    }
+}
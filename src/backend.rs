@@ -0,0 +1,265 @@
+use std::fmt;
+
+use anyhow::Context;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use futures::{FutureExt, future::LocalBoxFuture};
+use gemini_rust::Gemini;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    cli::{Backend, Model, Options},
+    structured,
+};
+
+/// A model transcribing the images of a note, whatever API it sits behind
+pub trait NoteConverter {
+    /// Send `note_images` along with `system_prompt` and answer with the model's
+    /// text. Backends without a thinking budget ignore `thinking_budget`.
+    fn convert<'a>(
+        &'a self,
+        system_prompt: String,
+        note_images: &'a [Vec<u8>],
+        mime: &'a str,
+        thinking_budget: Option<i32>,
+        options: &'a Options,
+    ) -> LocalBoxFuture<'a, anyhow::Result<String>>;
+}
+
+/// The converter `options` ask for, running `model` instead of the configured one
+/// when given
+pub fn converter(
+    options: &Options,
+    model: Option<Model>,
+) -> anyhow::Result<Box<dyn NoteConverter>> {
+    match options.backend {
+        Backend::Gemini => {
+            let key = options
+                .key
+                .clone()
+                .context("an API key is required, pass --key or set GEMINI_API_KEY")?;
+            let model = model.map_or_else(|| options.gemini_model(), Model::to_gemini_model);
+
+            Ok(Box::new(gemini_client(
+                key,
+                model,
+                options.base_url.clone(),
+            )?))
+        }
+        Backend::Openai => {
+            anyhow::ensure!(
+                model.is_none(),
+                "picking another model needs --backend gemini, the openai one only runs --model-name"
+            );
+            let base_url = options
+                .base_url
+                .clone()
+                .context("--backend openai needs the endpoint to talk to, pass --base-url")?;
+            let model = options
+                .model_name
+                .clone()
+                .context("--backend openai needs the model to run, pass --model-name")?;
+
+            Ok(Box::new(OpenAiClient::new(
+                base_url,
+                model,
+                options.key.clone(),
+            )?))
+        }
+    }
+}
+
+/// Stands in for the model during --dry-run, which stops every job before its
+/// request, so neither a key nor an endpoint is needed
+pub struct DryRun;
+
+impl NoteConverter for DryRun {
+    fn convert<'a>(
+        &'a self,
+        _system_prompt: String,
+        _note_images: &'a [Vec<u8>],
+        _mime: &'a str,
+        _thinking_budget: Option<i32>,
+        _options: &'a Options,
+    ) -> LocalBoxFuture<'a, anyhow::Result<String>> {
+        async { anyhow::bail!("a dry run sends no requests") }.boxed_local()
+    }
+}
+
+fn gemini_client(
+    key: String,
+    model: gemini_rust::Model,
+    base_url: Option<Url>,
+) -> anyhow::Result<Gemini> {
+    let client = match base_url {
+        Some(base_url) => Gemini::with_model_and_base_url(key, model, base_url)?,
+        None => Gemini::with_model(key, model)?,
+    };
+
+    Ok(client)
+}
+
+impl NoteConverter for Gemini {
+    fn convert<'a>(
+        &'a self,
+        system_prompt: String,
+        note_images: &'a [Vec<u8>],
+        mime: &'a str,
+        thinking_budget: Option<i32>,
+        options: &'a Options,
+    ) -> LocalBoxFuture<'a, anyhow::Result<String>> {
+        async move {
+            let request = self.generate_content();
+            let request = match thinking_budget {
+                None => request.with_dynamic_thinking(),
+                // Flash thinks dynamically unless told otherwise, so 0 is still needed
+                Some(tokens) => request.with_thinking_budget(tokens),
+            };
+            let request = match options.temperature {
+                Some(temperature) => request.with_temperature(temperature),
+                None => request,
+            };
+            let request = match options.max_output_tokens {
+                Some(tokens) => request.with_max_output_tokens(tokens),
+                None => request,
+            };
+            let request = if options.output_json {
+                request
+                    .with_response_mime_type("application/json")
+                    .with_response_schema(structured::schema())
+            } else {
+                request
+            };
+
+            let mut request = request.with_system_instruction(system_prompt);
+            for image in note_images {
+                request = request.with_inline_data(BASE64_STANDARD.encode(image), mime);
+            }
+
+            Ok(request.execute().await?.text())
+        }
+        .boxed_local()
+    }
+}
+
+/// Client of an OpenAI-compatible `/chat/completions` endpoint accepting images,
+/// such as a local vision model
+pub struct OpenAiClient {
+    http: reqwest::Client,
+    endpoint: Url,
+    model: String,
+    /// Sent as a bearer token; local servers usually don't want one
+    key: Option<String>,
+}
+
+impl OpenAiClient {
+    /// `base_url` ends with a slash, as --base-url makes sure of
+    pub fn new(base_url: Url, model: String, key: Option<String>) -> anyhow::Result<Self> {
+        let endpoint = base_url
+            .join("chat/completions")
+            .context("building the completions url")?;
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            endpoint,
+            model,
+            key,
+        })
+    }
+}
+
+impl NoteConverter for OpenAiClient {
+    fn convert<'a>(
+        &'a self,
+        system_prompt: String,
+        note_images: &'a [Vec<u8>],
+        mime: &'a str,
+        _thinking_budget: Option<i32>,
+        options: &'a Options,
+    ) -> LocalBoxFuture<'a, anyhow::Result<String>> {
+        async move {
+            let images: Vec<_> = note_images
+                .iter()
+                .map(|image| {
+                    let url = format!("data:{mime};base64,{}", BASE64_STANDARD.encode(image));
+                    serde_json::json!({ "type": "image_url", "image_url": { "url": url } })
+                })
+                .collect();
+
+            let mut body = serde_json::json!({
+                "model": self.model,
+                "messages": [
+                    { "role": "system", "content": system_prompt },
+                    { "role": "user", "content": images },
+                ],
+            });
+            if let Some(temperature) = options.temperature {
+                body["temperature"] = temperature.into();
+            }
+            if let Some(tokens) = options.max_output_tokens {
+                body["max_tokens"] = tokens.into();
+            }
+            if options.output_json {
+                body["response_format"] = serde_json::json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": "note", "schema": structured::schema() },
+                });
+            }
+
+            let mut request = self.http.post(self.endpoint.clone()).json(&body);
+            if let Some(key) = &self.key {
+                request = request.bearer_auth(key);
+            }
+
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(BadStatus {
+                    code: status.as_u16(),
+                    body,
+                }
+                .into());
+            }
+
+            let completion: Completion = response.json().await?;
+            completion
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .context("the completion has no message")
+        }
+        .boxed_local()
+    }
+}
+
+#[derive(Deserialize)]
+struct Completion {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    content: Option<String>,
+}
+
+/// An OpenAI-compatible endpoint answering with an error status
+#[derive(Debug)]
+pub struct BadStatus {
+    pub code: u16,
+    pub body: String,
+}
+
+impl fmt::Display for BadStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the endpoint answered {}: {}", self.code, self.body)
+    }
+}
+
+impl std::error::Error for BadStatus {}
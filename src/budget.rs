@@ -0,0 +1,43 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Hard cap on the model requests issued during a run, shared by all jobs
+pub struct RequestBudget {
+    remaining: AtomicUsize,
+}
+
+impl RequestBudget {
+    pub fn new(max_requests: usize) -> Self {
+        Self {
+            remaining: AtomicUsize::new(max_requests),
+        }
+    }
+
+    /// Take one request out of the budget, returning false once it's exhausted
+    pub fn try_take(&self) -> bool {
+        self.remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Whether the next [`Self::try_take`] fails
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// A model request refused because the budget ran out
+#[derive(Debug)]
+pub struct Exhausted;
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the request budget is exhausted")
+    }
+}
+
+impl std::error::Error for Exhausted {}
@@ -0,0 +1,59 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    cli::{Backend, ExportFormat, Model, Prompt},
+    encode::WEBP_MIME,
+    mime_type,
+};
+
+/// What this build supports, for tools wrapping it to adapt to the installed version
+#[derive(Serialize)]
+pub struct Capabilities {
+    version: &'static str,
+    providers: Vec<String>,
+    models: Vec<String>,
+    prompts: Vec<String>,
+    export_formats: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn of_this_build() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            providers: Backend::value_variants()
+                .iter()
+                .map(Backend::to_string)
+                .collect(),
+            models: Model::value_variants()
+                .iter()
+                .map(Model::to_string)
+                .collect(),
+            prompts: Prompt::value_variants()
+                .iter()
+                .filter_map(|prompt| prompt.to_possible_value())
+                .map(|value| value.get_name().to_owned())
+                .collect(),
+            export_formats: ExportFormat::value_variants()
+                .iter()
+                .map(|format| mime_type(format.to_selection_export_format()))
+                .chain([WEBP_MIME])
+                .collect(),
+        }
+    }
+
+    pub fn print(&self, json: bool) -> anyhow::Result<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+
+        println!("rnote-llm {}", self.version);
+        println!("providers: {}", self.providers.join(", "));
+        println!("models: {}", self.models.join(", "));
+        println!("prompts: {}", self.prompts.join(", "));
+        println!("export formats: {}", self.export_formats.join(", "));
+
+        Ok(())
+    }
+}
@@ -2,23 +2,133 @@ use core::fmt;
 use std::{borrow::Cow, path::PathBuf};
 
 use clap::{Subcommand, ValueEnum};
+use rnote_engine::engine::export::{SelectionExportFormat, SelectionExportPrefs};
+use url::Url;
+
+use crate::{paths::OutputNaming, shard::Shard};
 
 #[derive(Subcommand)]
 pub enum Command {
     Single {
         /// Input file
         file: PathBuf,
-        /// Optional output markdown file
+        /// Optional output markdown file, `-` for the standard output
         #[arg(required = false)]
         output_file: Option<PathBuf>,
     },
     Batch {
-        #[arg(short, default_value_t = 1)]
+        #[arg(short, default_value_t = 2)]
         /// Increase this value to enable recursive exploration of source subdirectories.
+        /// 1 only takes the notes right in the source folder, 2 (the default) goes one
+        /// subfolder deep too.
         max_depth: usize,
+        #[arg(long)]
+        /// Convert the notes listed in this file, one path per line, instead of
+        /// walking the source folder. Blank lines and `#` comments are ignored.
+        from_file: Option<PathBuf>,
+        #[arg(long, value_delimiter = ',', default_value = "rnote")]
+        /// Extensions of the files converted from the source folder, comma
+        /// separated. Other files are skipped.
+        extension: Vec<String>,
+        #[arg(long, conflicts_with = "from_file")]
+        /// Only convert the notes whose path relative to the source folder matches
+        /// this glob, e.g. `**/meetings/*.rnote`. Can be given multiple times.
+        include: Vec<String>,
+        #[arg(long, conflicts_with = "from_file")]
+        /// Skip the notes whose relative path matches this glob, even when an
+        /// --include matches them too. Can be given multiple times.
+        exclude: Vec<String>,
+        #[arg(long, default_value_t = false)]
+        /// Write every output right in the destination folder instead of mirroring
+        /// the subfolders of the source one. Notes that would end up with the same
+        /// output fail the run.
+        flatten: bool,
+        #[arg(long)]
+        /// Write all conversions to this one file instead, sorted by note path and
+        /// each under a heading naming its note
+        merge_into: Option<PathBuf>,
+        source_folder: PathBuf,
+        destination_folder: PathBuf,
+    },
+    /// Convert the given files, writing their outputs next to each other
+    Files {
+        #[arg(short, long)]
+        /// Folder receiving the markdown files, named after their inputs
+        output_dir: PathBuf,
+        #[arg(required = true)]
+        /// Input files
+        files: Vec<PathBuf>,
+    },
+    /// Rerun only the jobs recorded as failed by a previous batch
+    Retry {
+        /// Destination folder of the previous batch, containing its failures.txt
+        destination_folder: PathBuf,
+    },
+    /// Reconvert the notes of a previous batch whose output is suspiciously short
+    FixShort {
+        #[arg(long)]
+        /// Outputs with fewer characters than this get reconverted
+        min_len: usize,
+        #[arg(long, value_delimiter = ',', default_value = "rnote")]
+        /// Extensions the batch converted, as given to its --extension
+        extension: Vec<String>,
+        #[arg(long, default_value_t = false)]
+        /// The batch ran with --flatten, so notes are looked up by name anywhere
+        /// in the source folder
+        flatten: bool,
+        source_folder: PathBuf,
+        destination_folder: PathBuf,
+    },
+    /// Delete the outputs of a previous batch whose note was deleted since. Only
+    /// outputs written with --annotate are deleted, never hand-written ones.
+    Prune {
+        #[arg(long, default_value_t = false)]
+        /// Only list the outputs that would be deleted
+        dry_run: bool,
+        #[arg(long, value_delimiter = ',', default_value = "rnote")]
+        /// Extensions the batch converted, as given to its --extension
+        extension: Vec<String>,
+        #[arg(long, default_value_t = false)]
+        /// The batch ran with --flatten, so notes are looked up by name anywhere
+        /// in the source folder
+        flatten: bool,
         source_folder: PathBuf,
         destination_folder: PathBuf,
     },
+    /// Export a note to the image fed to the model, without converting it
+    Render {
+        /// Input file
+        file: PathBuf,
+        /// Output image file
+        output_file: PathBuf,
+    },
+    /// Serve a local HTTP API converting the notes POSTed to /convert
+    #[cfg(feature = "server")]
+    Serve {
+        #[arg(long, default_value_t = 8080)]
+        /// Port listened on, on localhost only
+        port: u16,
+    },
+    /// Print the version and the models, prompts and formats this build supports
+    Capabilities {
+        #[arg(long, default_value_t = false)]
+        /// Print them as JSON, for wrapping tools
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Backend {
+    /// Google's Gemini API, or a compatible one at --base-url
+    Gemini,
+    /// Any OpenAI-compatible `/chat/completions` endpoint accepting images
+    Openai,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
 }
 
 #[derive(ValueEnum, Clone, Copy)]
@@ -42,48 +152,404 @@ impl fmt::Display for Model {
     }
 }
 
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ExportFormat {
+    /// Lossless, best for dense diagrams
+    Png,
+    /// Lossy but much smaller, fine for most handwriting
+    Jpeg,
+}
+
+impl ExportFormat {
+    pub fn to_selection_export_format(self) -> SelectionExportFormat {
+        match self {
+            ExportFormat::Png => SelectionExportFormat::Png,
+            ExportFormat::Jpeg => SelectionExportFormat::Jpeg,
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
 #[derive(ValueEnum, Clone, Copy)]
 pub enum Prompt {
     Default,
     Summarize,
     Test,
+    /// Exact LaTeX transcription for equation-heavy notes
+    Math,
 }
 
-impl Options {
-    pub fn prompt(&self) -> std::io::Result<Cow<'static, str>> {
-        if let Some(custom) = &self.custom_prompt {
-            return std::fs::read_to_string(custom).map(Into::into);
-        }
-
+impl Prompt {
+    pub fn text(self) -> &'static str {
         const DEFAULT_PROMPT: &str = include_str!("./PROMPT.txt");
         const SUMMARIZE_PROMPT: &str = include_str!("./SUMMARIZE.txt");
         const TEST_PROMPT: &str = include_str!("./TEST.txt");
+        const MATH_PROMPT: &str = include_str!("./MATH.txt");
 
-        let prompt = match self.prompt {
+        match self {
             Prompt::Default => DEFAULT_PROMPT,
             Prompt::Summarize => SUMMARIZE_PROMPT,
             Prompt::Test => TEST_PROMPT,
+            Prompt::Math => MATH_PROMPT,
+        }
+    }
+}
+
+impl Options {
+    pub fn prompt(&self) -> std::io::Result<Cow<'static, str>> {
+        if let Some(text) = &self.prompt_text {
+            return Ok(normalize_prompt(text).into());
+        }
+
+        if let Some(custom) = &self.custom_prompt {
+            let custom = std::fs::read_to_string(custom)?;
+            return Ok(normalize_prompt(&custom).into());
+        }
+
+        const PREVIEW_PROMPT: &str = include_str!("./PREVIEW.txt");
+
+        if self.preview && self.prompt_name.is_none() {
+            return Ok(PREVIEW_PROMPT.into());
+        }
+
+        let builtin = match &self.prompt_name {
+            // reserved names keep resolving to the built-in prompts
+            Some(name) => match Prompt::from_str(name, true) {
+                Ok(builtin) => builtin,
+                Err(_) => return self.named_prompt(name).map(Into::into),
+            },
+            None => self.prompt,
+        };
+
+        Ok(builtin.text().into())
+    }
+
+    /// What --language tells the model, ready to append to the system prompt.
+    /// Empty without --language, leaving the prompts as they are.
+    pub fn language_directive(&self) -> String {
+        match &self.language {
+            Some(language) => format!(
+                "\n\nThe handwriting is in {language}; transcribe faithfully without translating."
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Contents of the --context-file files, delimited and ready to append to the system prompt
+    pub fn prompt_context(&self) -> std::io::Result<String> {
+        const MAX_CONTEXT_BYTES: usize = 64 * 1024;
+
+        let mut context = String::new();
+
+        for path in &self.context_files {
+            let contents = std::fs::read_to_string(path).map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("reading context file {}: {err}", path.display()),
+                )
+            })?;
+
+            let name = path.display();
+            context.push_str(&format!(
+                "\n\n--- BEGIN CONTEXT: {name} ---\n{}\n--- END CONTEXT: {name} ---",
+                normalize_prompt(&contents).trim_end()
+            ));
+        }
+
+        if context.is_empty() {
+            return Ok(context);
+        }
+
+        if context.len() > MAX_CONTEXT_BYTES {
+            log::warn!(
+                "context files take {} bytes, truncating them to {MAX_CONTEXT_BYTES}",
+                context.len()
+            );
+
+            let mut end = MAX_CONTEXT_BYTES;
+            while !context.is_char_boundary(end) {
+                end -= 1;
+            }
+            context.truncate(end);
+        }
+
+        Ok(format!(
+            "\n\nCONTEXT\n- The following reference material helps with the terminology of the notes. \
+             Use it to read them correctly, do not transcribe it.{context}"
+        ))
+    }
+
+    fn named_prompt(&self, name: &str) -> std::io::Result<String> {
+        let path = self.prompts_dir.join(format!("{name}.txt"));
+
+        let prompt = std::fs::read_to_string(&path).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("reading prompt `{name}` from {}: {err}", path.display()),
+            )
+        })?;
+
+        Ok(normalize_prompt(&prompt))
+    }
+
+    /// The model to run, with --model-name taking precedence over --model
+    pub fn gemini_model(&self) -> gemini_rust::Model {
+        match &self.model_name {
+            // builtin models are addressed as `models/<name>` too
+            Some(name) if name.starts_with("models/") => gemini_rust::Model::Custom(name.clone()),
+            Some(name) => gemini_rust::Model::Custom(format!("models/{name}")),
+            None => self.model.to_gemini_model(),
+        }
+    }
+
+    /// Identifier of the model to run, for recording it alongside the outputs
+    pub fn model_id(&self) -> String {
+        self.model_name
+            .clone()
+            .unwrap_or_else(|| self.model.to_string())
+    }
+
+    /// Name of the system prompt [`Options::prompt`] picks, for recording it
+    /// alongside the outputs
+    pub fn prompt_label(&self) -> String {
+        if self.prompt_text.is_some() {
+            return "inline".to_owned();
+        }
+        if let Some(custom) = &self.custom_prompt {
+            return custom.display().to_string();
+        }
+
+        match &self.prompt_name {
+            Some(name) => name.clone(),
+            None if self.preview => "preview".to_owned(),
+            None => self.prompt.to_string(),
+        }
+    }
+
+    /// How many tokens the model gets to think before answering, `None` for as
+    /// many as it likes. See [`Thinking`].
+    pub fn thinking_budget(&self) -> Option<i32> {
+        let flash = match &self.model_name {
+            Some(name) => name.contains("flash"),
+            None => matches!(self.model, Model::Gemini25Flash),
+        };
+
+        self.thinking_budget_for(flash)
+    }
+
+    /// [`Options::thinking_budget`] for a Flash model, or any other
+    pub fn thinking_budget_for(&self, flash: bool) -> Option<i32> {
+        match self.thinking_budget {
+            Some(tokens) => Some(tokens),
+            None => (!self.thinking.enabled_for(flash)).then_some(0),
+        }
+    }
+
+    pub fn export_settings(&self) -> ExportSettings {
+        self.export_settings_scaled(self.scale())
+    }
+
+    /// How outputs are named after their notes
+    pub fn output_naming(&self) -> OutputNaming {
+        OutputNaming {
+            extension: if self.output_json {
+                "json".to_owned()
+            } else {
+                self.out_extension.clone()
+            },
+            suffix: self.out_suffix.clone(),
+            flatten: false,
+        }
+    }
+
+    /// Multiplier of the native export resolution, given by --dpi or --scale
+    pub fn scale(&self) -> f64 {
+        match self.dpi {
+            Some(dpi) => dpi / (DOCUMENT_DPI * SelectionExportPrefs::default().bitmap_scalefactor),
+            None => self.scale,
+        }
+    }
+
+    /// [`Options::export_settings`] with `scale` in place of --scale
+    pub fn export_settings_scaled(&self, scale: f64) -> ExportSettings {
+        let defaults = SelectionExportPrefs::default();
+
+        let prefs = SelectionExportPrefs {
+            // a pattern over a transparent page would come out as loose lines
+            with_background: self.with_background || self.with_pattern,
+            with_pattern: self.with_pattern,
+            optimize_printing: false,
+            export_format: self.export_format.to_selection_export_format(),
+            // a huge scale would render images of gigabytes
+            bitmap_scalefactor: (defaults.bitmap_scalefactor * scale).min(MAX_DPI / DOCUMENT_DPI),
+            ..defaults
         };
 
-        Ok(prompt.into())
+        ExportSettings {
+            prefs,
+            first_page_only: self.preview,
+            per_page: self.per_page,
+            include_trashed: self.include_trashed,
+            background_color: self.background_color,
+        }
     }
 }
 
+/// Resolution of the document coordinates, which are in pixels at this DPI
+const DOCUMENT_DPI: f64 = 96.0;
+
+/// Highest resolution exported at, whatever --scale or --dpi ask for
+const MAX_DPI: f64 = 600.0;
+
+/// What to export from a note, and how
+#[derive(Clone)]
+pub struct ExportSettings {
+    pub prefs: SelectionExportPrefs,
+    pub first_page_only: bool,
+    /// Export every page on its own, see --per-page
+    pub per_page: bool,
+    /// Also export strokes that were deleted but are still stored in the note
+    pub include_trashed: bool,
+    /// RGBA page background replacing the note's own
+    pub background_color: Option<[f64; 4]>,
+}
+
+/// Strip the UTF-8 BOM and CRLF line endings that prompt files written on Windows may carry
+pub fn normalize_prompt(prompt: &str) -> String {
+    prompt
+        .strip_prefix('\u{feff}')
+        .unwrap_or(prompt)
+        .replace("\r\n", "\n")
+}
+
 impl fmt::Display for Prompt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.to_possible_value().unwrap().get_name().fmt(f)
     }
 }
 
+#[derive(ValueEnum, Clone, Copy)]
+pub enum FinalNewline {
+    /// Leave the output as the model returned it
+    Keep,
+    /// End the output with exactly one newline
+    Add,
+    /// Remove every trailing newline
+    Strip,
+}
+
+impl FinalNewline {
+    pub fn apply(self, text: String) -> String {
+        const NEWLINES: [char; 2] = ['\r', '\n'];
+
+        match self {
+            FinalNewline::Keep => text,
+            FinalNewline::Add => format!("{}\n", text.trim_end_matches(NEWLINES)),
+            FinalNewline::Strip => text.trim_end_matches(NEWLINES).to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for FinalNewline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Thinking {
+    /// Think dynamically, except on Flash models where plain transcription doesn't
+    /// gain enough from it to be worth the latency
+    Auto,
+    /// Always think dynamically
+    On,
+    /// Never think. Pro models don't support this.
+    Off,
+}
+
+impl Thinking {
+    pub fn enabled_for(self, flash: bool) -> bool {
+        match self {
+            Thinking::Auto => !flash,
+            Thinking::On => true,
+            Thinking::Off => false,
+        }
+    }
+}
+
+impl fmt::Display for Thinking {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum ConcatOrder {
+    /// Lexicographic order of the source paths
+    Path,
+    /// Oldest source note first
+    Mtime,
+    /// Source paths compared with their digit runs as numbers, so `day2` comes
+    /// before `day10`
+    Numeric,
+}
+
+impl fmt::Display for ConcatOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
+
 #[derive(clap::Parser)]
 pub struct Options {
-    #[arg(short, long, required = true)]
-    /// Gemini AI Studio API key
-    pub key: String,
+    #[arg(long)]
+    /// TOML file of options for the whole run, keyed like `skip_existing = true`
+    /// or `model = "gemini25-pro"`, which the command line overrides. Defaults to
+    /// rnote-llm.toml in the current directory, when there is one.
+    pub config: Option<PathBuf>,
+
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    /// Log more, debug messages with -v and trace ones with -vv. RUST_LOG still
+    /// refines what gets logged.
+    pub verbose: u8,
+
+    #[arg(long)]
+    /// Append the log to this file too
+    pub log_file: Option<PathBuf>,
+
+    #[arg(short, long, env = "GEMINI_API_KEY", hide_env_values = true)]
+    /// Gemini AI Studio API key, required by every command but render. Prefer the
+    /// environment variable, which stays out of the shell history and process list.
+    pub key: Option<String>,
+
+    #[arg(long, default_value_t = Backend::Gemini)]
+    /// API the notes are sent to. The openai backend talks to the endpoint at
+    /// --base-url, running --model-name, and only sends --key when given one.
+    pub backend: Backend,
 
     #[arg(short, long, default_value_t = Model::Gemini25Flash)]
     pub model: Model,
 
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    /// Raw Gemini model identifier overriding --model, for models this version
+    /// doesn't know about yet
+    pub model_name: Option<String>,
+
+    #[arg(long)]
+    /// Model to convert a note with when the primary one still fails after every
+    /// retry, e.g. when Pro runs out of quota. Invalid keys don't fall back.
+    pub fallback_model: Option<Model>,
+
+    #[arg(long, value_parser = parse_base_url)]
+    /// Endpoint of a Gemini-compatible API to use instead of Google's, or of the
+    /// OpenAI-compatible one with --backend openai, e.g. `http://localhost:8080/v1`
+    pub base_url: Option<Url>,
+
     #[arg(short, long, default_value_t = Prompt::Default)]
     pub prompt: Prompt,
 
@@ -92,10 +558,538 @@ pub struct Options {
     /// to sync up your llm generated notes to your new handwritten notes.
     pub skip_existing: bool,
 
+    #[arg(long, default_value_t = false)]
+    /// Skip the notes whose output was written after the note last changed, for
+    /// incremental syncs
+    pub skip_unchanged: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Print which output every note would be converted to, and whether it would
+    /// be skipped, without exporting, converting or writing anything. Needs no API
+    /// key or endpoint.
+    pub dry_run: bool,
+
     #[arg(short, long, required = false)]
     /// If specified, a path to a text file containing the system prompt
     pub custom_prompt: Option<PathBuf>,
 
+    #[arg(long, conflicts_with = "custom_prompt")]
+    /// The system prompt itself, for one-off tweaks without a file
+    pub prompt_text: Option<String>,
+
+    #[arg(long)]
+    /// Use `<prompts-dir>/<PROMPT_NAME>.txt` as the system prompt. The names of the
+    /// built-in prompts still select them.
+    pub prompt_name: Option<String>,
+
+    #[arg(long, default_value = "prompts")]
+    /// Directory searched by --prompt-name
+    pub prompts_dir: PathBuf,
+
+    #[arg(long, value_parser = parse_image_mime)]
+    /// Override the MIME type sent along with the exported image, which otherwise
+    /// follows the export format.
+    pub image_mime: Option<String>,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Soft limit, in MB, on exported images held in memory at once. New exports
+    /// wait while the in-flight images exceed it.
+    pub max_memory: Option<u32>,
+
+    #[arg(long, default_value_t = false)]
+    /// Mark generated files, and refuse to overwrite existing files lacking the mark
+    /// since they are likely hand-written notes.
+    pub annotate: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Always reconvert, the opposite of -s: existing outputs are overwritten even
+    /// with -s, or when --annotate can't tell they were generated
+    pub force: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Process jobs in random order, evening out progress across large trees
+    pub shuffle: bool,
+
+    #[arg(long, requires = "shuffle")]
+    /// Seed for --shuffle, making the order reproducible
+    pub seed: Option<u64>,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_scale)]
+    /// Multiplier applied to the note's native export resolution, capped at 600 DPI
+    pub scale: f64,
+
+    #[arg(long, conflicts_with = "scale", value_parser = parse_dpi)]
+    /// Export resolution in DPI, up to 600, instead of a --scale multiplier
+    pub dpi: Option<f64>,
+
+    #[arg(long, default_value_t = 0)]
+    /// Delay in milliseconds between starting successive jobs, keeping long batches
+    /// from saturating the machine
+    pub cooldown_ms: u64,
+
+    #[arg(long)]
+    /// Stop issuing model requests after this many, skipping the remaining jobs
+    pub max_requests: Option<usize>,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Start at most this many model requests per minute across all jobs, e.g. to
+    /// stay within the cap of a free-tier key. --concurrency still bounds how
+    /// many are in flight.
+    pub rpm: Option<u32>,
+
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Abort the run once this many jobs fail in a row
+    pub max_consecutive_failures: Option<u64>,
+
+    #[arg(long, default_value_t = false)]
+    /// Abort the run at the first failed job, instead of converting the rest
+    pub fail_fast: bool,
+
+    #[arg(long, default_value_t = FinalNewline::Add)]
+    /// How to handle trailing newlines of the written markdown
+    pub final_newline: FinalNewline,
+
+    #[arg(long, default_value_t = false)]
+    /// Trim the empty page around the strokes of the exported image, leaving a
+    /// small margin, so the handwriting fills more of what the model sees
+    pub autocrop: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Re-encode the exported image as WebP before sending it, shrinking the payload
+    pub webp: bool,
+
+    #[arg(long, default_value_t = 90.0, value_parser = parse_webp_quality)]
+    /// WebP quality, from 0 to 100, used with --webp
+    pub webp_quality: f32,
+
+    #[arg(long)]
+    /// Keep a JSON snapshot of the overall progress at this path, for headless runs
+    pub progress_file: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Write how every job ended to this JSON file at the end of the run, with the
+    /// reason of every failure
+    pub report: Option<PathBuf>,
+
+    #[arg(long = "context-file")]
+    /// Text file, such as a glossary, appended to the system prompt as reference
+    /// material. Can be repeated.
+    pub context_files: Vec<PathBuf>,
+
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    /// Language the notes are written in, e.g. `Italian`, keeping the model from
+    /// "correcting" its words into English
+    pub language: Option<String>,
+
+    #[arg(long, default_value_t = 1000)]
+    /// Delay in milliseconds before the first retry of a failed request, doubling
+    /// on every further retry. A delay requested by the server takes precedence.
+    pub retry_base_ms: u64,
+
+    #[arg(long, default_value_t = 60_000)]
+    /// Upper bound in milliseconds for the delay between retries
+    pub retry_max_ms: u64,
+
+    #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Seconds a model request may take before it fails and gets retried
+    pub timeout_secs: u64,
+
+    #[arg(long)]
+    /// Append every conversion to this file under a dated heading, instead of
+    /// writing one file per note
+    pub append_to: Option<PathBuf>,
+
+    #[arg(long, requires = "append_to")]
+    /// Order of the sections in --append-to. By default they are appended as
+    /// conversions finish; with an order they are held back until the end of the
+    /// run and appended sorted.
+    pub concat_order: Option<ConcatOrder>,
+
+    #[arg(long, default_value_t = false)]
+    /// Write gzip compressed `<name>.md.gz` outputs
+    pub gzip: bool,
+
+    #[arg(long, default_value = "md", value_parser = parse_name_part)]
+    /// Extension of the outputs named after their notes, e.g. `markdown` or `txt`
+    pub out_extension: String,
+
+    #[arg(long, value_parser = parse_name_part)]
+    /// Inserted before the extension of the outputs named after their notes, so
+    /// `lecture.rnote` becomes `lecture.<SUFFIX>.md`
+    pub out_suffix: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// Survey notes cheaply: only their first page is exported, and summarized with a
+    /// lightweight prompt unless --custom-prompt or --prompt-name is given. The
+    /// output is not a complete transcription.
+    pub preview: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with = "reuse_export")]
+    /// Export every page of a note as an image of its own and send them all,
+    /// instead of one tall image of the whole note. The pages are separated by a
+    /// horizontal rule in the output. Blank pages are left out.
+    pub per_page: bool,
+
+    #[arg(long)]
+    /// Directory for temporary files written while exporting, created if missing.
+    /// Useful where the default temporary directory is read-only.
+    pub tmp_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    /// Also transcribe deleted strokes still stored in the notes, for auditing
+    pub include_trashed: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Treat model output stuck repeating the same lines as a failed, retried
+    /// request instead of only warning about it
+    pub fail_on_loop: bool,
+
+    #[arg(long, value_parser = parse_shard)]
+    /// Only process the jobs of shard `<INDEX>/<TOTAL>`, e.g. `0/3` on the first of
+    /// three hosts. Every host must be given the same inputs.
+    pub shard: Option<Shard>,
+
+    #[arg(long, default_value_t = false)]
+    /// Also write `<name>.stats.json` next to every output, with the stroke count,
+    /// total length, bounding box and colors of the exported strokes, and the
+    /// time spent in each stage of the job
+    pub stats_sidecar: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Check that every note loads before converting any of them, failing the run
+    /// up front if one doesn't
+    pub preflight: bool,
+
+    #[arg(long, default_value_t = false, requires = "preflight")]
+    /// Drop the notes failing the preflight instead of failing the run
+    pub skip_invalid: bool,
+
+    #[arg(long, value_parser = parse_mode)]
+    /// Octal permissions of the folders created for the outputs, e.g. 755. Unix only.
+    pub dir_mode: Option<u32>,
+
+    #[arg(long, value_parser = parse_mode)]
+    /// Octal permissions of the written outputs, e.g. 644. Unix only.
+    pub file_mode: Option<u32>,
+
+    #[arg(long, default_value_t = false, conflicts_with = "stats_sidecar")]
+    /// Keep every export in the temporary directory and reuse it while the note
+    /// doesn't change, e.g. when tuning the prompt for one note. An export is
+    /// reused when the note's bytes, the scale, the image format, --preview,
+    /// --include-trashed, --background-color, --with-background, --with-pattern
+    /// and --cache-salt all match.
+    pub reuse_export: bool,
+
+    #[arg(long)]
+    /// Arbitrary string mixed into the keys of --reuse-export and --cache-dir;
+    /// changing it ignores every export and conversion cached under another salt
+    /// without deleting them
+    pub cache_salt: Option<String>,
+
+    #[arg(long)]
+    /// Keep every conversion in this folder and reuse it for notes exporting to the
+    /// same image, as long as the prompt, model, backend, --thinking,
+    /// --thinking-budget, --temperature, --max-output-tokens, --passes and
+    /// --output-json and --cache-salt match too
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = Thinking::Auto)]
+    /// Whether the model thinks before transcribing
+    pub thinking: Thinking,
+
+    #[arg(
+        long,
+        conflicts_with = "thinking",
+        value_parser = clap::value_parser!(i32).range(1..)
+    )]
+    /// Let the model think for at most this many tokens instead of dynamically,
+    /// e.g. for dense math
+    pub thinking_budget: Option<i32>,
+
+    #[arg(long, value_parser = parse_temperature)]
+    /// Sampling temperature, from 0 to 2. Close to 0 copies the note faithfully,
+    /// higher values suit loose prompts such as summaries. Defaults to the
+    /// model's own.
+    pub temperature: Option<f32>,
+
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    /// Cut every answer off after this many tokens, bounding runaway outputs
+    pub max_output_tokens: Option<i32>,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Convert every note this many times and keep the transcription most passes
+    /// agree on. Multiplies the requests, and their cost, by the same factor.
+    pub passes: u32,
+
+    #[arg(long, default_value_t = false)]
+    /// Warn about unclosed code fences, ragged tables and unbalanced math
+    /// delimiters in the outputs
+    pub validate_md: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Like --validate-md, also closing dangling fences and padding table rows
+    pub fix_md: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Show every conversion and ask whether to write it, reject it or retry it
+    /// with another prompt or model. Jobs run one at a time, and only when stdout
+    /// is a terminal.
+    pub interactive: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Export at half of --scale first, and only go back up to it, once, when the
+    /// transcription comes out shorter than --adaptive-min-len or stuck in a loop
+    pub adaptive_dpi: bool,
+
+    #[arg(long, default_value_t = 200, requires = "adaptive_dpi")]
+    /// Characters below which --adaptive-dpi retries at full resolution
+    pub adaptive_min_len: usize,
+
+    #[arg(long, value_parser = parse_hex_color)]
+    /// Page background used for the export, e.g. `#ffffff` to maximize contrast
+    /// on notes taken on colored paper
+    pub background_color: Option<[f64; 4]>,
+
+    #[arg(long, default_value_t = false)]
+    /// Start every output with YAML front matter naming its note, the conversion
+    /// time, the model and the prompt, unless the model already wrote some
+    pub front_matter: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "out_extension",
+            "annotate",
+            "front_matter",
+            "append_to",
+            "validate_md",
+            "fix_md"
+        ]
+    )]
+    /// Have the model answer with JSON holding the note's title, transcription,
+    /// headings and a word on its confidence, written as `<name>.json`. Answers
+    /// not following that shape fail their job.
+    pub output_json: bool,
+
+    #[arg(
+        short = 'j',
+        long,
+        default_value_t = 10,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    /// Number of notes converted at once. Every one of them holds an engine and its
+    /// export in memory, so lower this on small machines.
+    pub concurrency: usize,
+
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    /// Export the page background. Turning it off exports transparent images,
+    /// which can bring out faint pencil strokes.
+    pub with_background: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Export the background pattern, such as a grid, which helps the model with
+    /// tables and graphs. Implies --with-background.
+    pub with_pattern: bool,
+
+    #[arg(long, default_value_t = ExportFormat::Png)]
+    /// Image format the notes are exported to, and sent to the model in unless
+    /// --webp re-encodes them
+    pub export_format: ExportFormat,
+
     #[command(subcommand)]
     pub command: Command,
 }
+
+fn parse_image_mime(mime: &str) -> Result<String, String> {
+    match mime.strip_prefix("image/") {
+        Some(subtype) if !subtype.is_empty() => Ok(mime.to_owned()),
+        _ => Err(format!("`{mime}` is not an image/* MIME type")),
+    }
+}
+
+fn parse_scale(scale: &str) -> Result<f64, String> {
+    let scale: f64 = scale.parse().map_err(|err| format!("{err}"))?;
+
+    if scale.is_finite() && scale > 0.0 {
+        Ok(scale)
+    } else {
+        Err(format!("scale must be a positive number, got {scale}"))
+    }
+}
+
+/// A piece of a file name, such as an extension, without leading dots
+fn parse_name_part(part: &str) -> Result<String, String> {
+    let part = part.trim_start_matches('.');
+
+    if part.is_empty() || part.contains(['/', '\\']) {
+        Err(format!("{part:?} can't be part of a file name"))
+    } else {
+        Ok(part.to_owned())
+    }
+}
+
+fn parse_dpi(dpi: &str) -> Result<f64, String> {
+    let dpi: f64 = dpi.parse().map_err(|err| format!("{err}"))?;
+
+    if dpi.is_finite() && dpi > 0.0 && dpi <= MAX_DPI {
+        Ok(dpi)
+    } else {
+        Err(format!(
+            "DPI must be above 0 and at most {MAX_DPI}, got {dpi}"
+        ))
+    }
+}
+
+/// Highest temperature the Gemini models accept
+const MAX_TEMPERATURE: f32 = 2.0;
+
+fn parse_temperature(temperature: &str) -> Result<f32, String> {
+    let temperature: f32 = temperature.parse().map_err(|err| format!("{err}"))?;
+
+    if (0.0..=MAX_TEMPERATURE).contains(&temperature) {
+        Ok(temperature)
+    } else {
+        Err(format!(
+            "temperature must be between 0 and {MAX_TEMPERATURE}, got {temperature}"
+        ))
+    }
+}
+
+fn parse_webp_quality(quality: &str) -> Result<f32, String> {
+    let quality: f32 = quality.parse().map_err(|err| format!("{err}"))?;
+
+    if (0.0..=100.0).contains(&quality) {
+        Ok(quality)
+    } else {
+        Err(format!("quality must be between 0 and 100, got {quality}"))
+    }
+}
+
+/// `#rrggbb` or `#rrggbbaa`, the `#` being optional
+fn parse_hex_color(color: &str) -> Result<[f64; 4], String> {
+    let digits = color.strip_prefix('#').unwrap_or(color);
+    if !matches!(digits.len(), 6 | 8) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "expected a #rrggbb or #rrggbbaa color, got {color}"
+        ));
+    }
+
+    let mut rgba = [1.0; 4];
+    for (channel, value) in rgba.iter_mut().enumerate().take(digits.len() / 2) {
+        let byte = u8::from_str_radix(&digits[channel * 2..channel * 2 + 2], 16)
+            .map_err(|err| format!("{err}"))?;
+        *value = f64::from(byte) / 255.0;
+    }
+
+    Ok(rgba)
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    let mode = u32::from_str_radix(digits, 8).map_err(|err| format!("{err}"))?;
+
+    if mode > 0o7777 {
+        return Err(format!("{mode:o} is not a valid permission mode"));
+    }
+
+    Ok(mode)
+}
+
+fn parse_shard(shard: &str) -> Result<Shard, String> {
+    let (index, total) = shard
+        .split_once('/')
+        .ok_or_else(|| format!("expected <INDEX>/<TOTAL>, got {shard}"))?;
+
+    let index: u64 = index.trim().parse().map_err(|err| format!("{err}"))?;
+    let total: u64 = total.trim().parse().map_err(|err| format!("{err}"))?;
+
+    if index >= total {
+        return Err(format!(
+            "shard index {index} must be below the total {total}"
+        ));
+    }
+
+    Ok(Shard { index, total })
+}
+
+fn parse_base_url(base_url: &str) -> Result<Url, String> {
+    let mut base_url = Url::parse(base_url).map_err(|err| format!("{err}"))?;
+
+    if !matches!(base_url.scheme(), "http" | "https") {
+        return Err(format!("expected an http(s) url, got {base_url}"));
+    }
+
+    // model paths are joined onto the base, which would otherwise replace its last segment
+    if !base_url.path().ends_with('/') {
+        let path = format!("{}/", base_url.path());
+        base_url.set_path(&path);
+    }
+
+    Ok(base_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn final_newline_keep_leaves_text_alone() {
+        assert_eq!(FinalNewline::Keep.apply("text\n\n".into()), "text\n\n");
+        assert_eq!(FinalNewline::Keep.apply("text".into()), "text");
+    }
+
+    #[test]
+    fn final_newline_add_ends_with_exactly_one() {
+        assert_eq!(FinalNewline::Add.apply("text".into()), "text\n");
+        assert_eq!(FinalNewline::Add.apply("text\n".into()), "text\n");
+        assert_eq!(FinalNewline::Add.apply("text\r\n\r\n\n".into()), "text\n");
+        assert_eq!(FinalNewline::Add.apply(String::new()), "\n");
+    }
+
+    #[test]
+    fn final_newline_strip_removes_every_trailing_one() {
+        assert_eq!(FinalNewline::Strip.apply("text\r\n\n".into()), "text");
+        assert_eq!(FinalNewline::Strip.apply("a\n\nb".into()), "a\n\nb");
+        assert_eq!(FinalNewline::Strip.apply("\n\n".into()), "");
+    }
+
+    #[test]
+    fn final_newline_keeps_trailing_spaces() {
+        assert_eq!(FinalNewline::Add.apply("text  \n".into()), "text  \n");
+        assert_eq!(FinalNewline::Strip.apply("text \n".into()), "text ");
+    }
+
+    #[test]
+    fn normalize_prompt_strips_the_bom() {
+        assert_eq!(normalize_prompt("\u{feff}Transcribe"), "Transcribe");
+        // only a leading one is a BOM
+        assert_eq!(normalize_prompt("a\u{feff}b"), "a\u{feff}b");
+    }
+
+    #[test]
+    fn normalize_prompt_converts_crlf() {
+        assert_eq!(normalize_prompt("\u{feff}one\r\ntwo\r\n"), "one\ntwo\n");
+        // lone carriage returns aren't line endings
+        assert_eq!(normalize_prompt("one\rtwo\n"), "one\rtwo\n");
+    }
+
+    #[test]
+    fn parse_hex_color_reads_rgb_and_rgba() {
+        assert_eq!(parse_hex_color("#ff0000"), Ok([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_hex_color("00FF00"), Ok([0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(
+            parse_hex_color("#0000ff80"),
+            Ok([0.0, 0.0, 1.0, 128.0 / 255.0])
+        );
+    }
+
+    #[test]
+    fn parse_hex_color_rejects_other_forms() {
+        for color in [
+            "", "#", "#fff", "#ff00000", "##ff0000", "#gg0000", "#ff00é0", "red",
+        ] {
+            assert!(parse_hex_color(color).is_err(), "{color} was accepted");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
+
+use crate::cli::Options;
+
+/// Config file picked up from the current directory when --config isn't given
+pub const DEFAULT_CONFIG: &str = "rnote-llm.toml";
+
+/// Parse the command line, filling in the options it leaves out from the config
+/// file. Options given on the command line or through their environment
+/// variable win over the file, as do the ones conflicting with them.
+pub fn parse() -> anyhow::Result<Options> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let command = Options::command();
+    let matches = command.clone().get_matches_from(&args);
+
+    let config = match matches.get_one::<PathBuf>("config") {
+        Some(config) => Some(config.clone()),
+        None => Some(PathBuf::from(DEFAULT_CONFIG)).filter(|config| config.is_file()),
+    };
+    let Some(config) = config else {
+        return Ok(Options::from_arg_matches(&matches).unwrap_or_else(|err| err.exit()));
+    };
+
+    let mut config_args = vec![];
+    for (key, value) in read(&config)? {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str() && key != "config")
+            .with_context(|| format!("unknown option {key} in {}", config.display()))?;
+
+        let explicit = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        // conflicts are declared on either side, so look both ways
+        let overridden = command.get_arguments().any(|other| {
+            explicit(other.get_id().as_str())
+                && (command
+                    .get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == other.get_id())
+                    || command
+                        .get_arg_conflicts_with(other)
+                        .iter()
+                        .any(|conflict| conflict.get_id() == arg.get_id()))
+        });
+        if explicit(&key) || overridden {
+            continue;
+        }
+
+        let flag = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => unreachable!("options are never positional"),
+        };
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match (arg.get_action(), value) {
+                (ArgAction::SetTrue, toml::Value::Boolean(true)) => config_args.push(flag.clone()),
+                (ArgAction::SetTrue, toml::Value::Boolean(false)) => {}
+                (ArgAction::SetTrue, _) => {
+                    anyhow::bail!("{key} in {} must be true or false", config.display())
+                }
+                (ArgAction::Count, toml::Value::Integer(count)) => {
+                    config_args.extend((0..count).map(|_| flag.clone()))
+                }
+                // `=` keeps values starting with a dash from passing for flags
+                (_, toml::Value::String(value)) => config_args.push(format!("{flag}={value}")),
+                (_, value) => config_args.push(format!("{flag}={value}")),
+            }
+        }
+    }
+
+    // placed before the subcommand, where the options of the whole run go
+    let args = args
+        .iter()
+        .take(1)
+        .cloned()
+        .chain(config_args.into_iter().map(OsString::from))
+        .chain(args.iter().skip(1).cloned());
+
+    Ok(Options::parse_from(args))
+}
+
+fn read(config: &Path) -> anyhow::Result<toml::Table> {
+    let contents = std::fs::read_to_string(config)
+        .with_context(|| format!("reading config file {}", config.display()))?;
+
+    toml::from_str(&contents).with_context(|| format!("parsing config file {}", config.display()))
+}
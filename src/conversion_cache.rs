@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use crate::{cli::Options, stable_hash::KeyHasher};
+
+/// Conversions kept across runs with `--cache-dir`, so identical notes aren't
+/// sent to the model again
+pub struct ConversionCache {
+    dir: PathBuf,
+}
+
+impl ConversionCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Identifies the conversion of `images`, changing with the images themselves, the
+    /// system prompt, the model, the backend serving it and anything else steering
+    /// what the model answers.
+    /// Unlike the note's mtime it survives touching or re-cloning the notes, and
+    /// upgrading rnote-llm.
+    pub fn key(images: &[Vec<u8>], mime: &str, system_prompt: &str, options: &Options) -> String {
+        let mut hasher = KeyHasher::new();
+        hasher.field((images.len() as u64).to_le_bytes());
+        for image in images {
+            hasher.field(image);
+        }
+        hasher
+            .field(mime)
+            .field(system_prompt)
+            .field(options.model_id())
+            .optional_field(options.thinking_budget().map(i32::to_le_bytes))
+            .optional_field(
+                options
+                    .temperature
+                    .map(|temperature| temperature.to_bits().to_le_bytes()),
+            )
+            .optional_field(options.max_output_tokens.map(i32::to_le_bytes))
+            .field(options.passes.to_le_bytes())
+            .field([u8::from(options.output_json)])
+            .field(options.backend.to_string())
+            .optional_field(options.base_url.as_ref().map(|url| url.as_str()))
+            .optional_field(options.cache_salt.as_deref());
+
+        hasher.finish()
+    }
+
+    /// The markdown cached for `key`, if a previous run stored one
+    pub async fn load(&self, key: &str) -> Option<String> {
+        tokio::fs::read_to_string(self.dir.join(key)).await.ok()
+    }
+
+    pub async fn store(&self, key: &str, markdown: &str) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.dir.join(key), markdown).await
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Context;
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage, imageops};
+
+pub const WEBP_MIME: &str = "image/webp";
+
+/// Pixels of background left around the strokes by [`autocrop`]
+pub const AUTOCROP_MARGIN: u32 = 16;
+
+/// Largest difference of a channel, such as JPEG noise, still counted as background
+const BACKGROUND_TOLERANCE: u8 = 16;
+
+/// Trim the background around the strokes of an exported image, taking the color
+/// of its top left pixel as the background, then pad it back with `margin` pixels
+/// of it. The image keeps its format, and comes back unchanged when blank.
+pub fn autocrop(image: &[u8], margin: u32) -> anyhow::Result<Vec<u8>> {
+    let format = image::guess_format(image).context("detecting exported image format")?;
+    let rgba = image::load_from_memory_with_format(image, format)
+        .context("decoding exported image")?
+        .to_rgba8();
+
+    let Some(&background) = rgba.get_pixel_checked(0, 0) else {
+        return Ok(image.to_vec());
+    };
+    let is_background = |pixel: &Rgba<u8>| {
+        // whatever color a fully transparent pixel claims, it shows nothing
+        (background[3] == 0 && pixel[3] == 0)
+            || pixel
+                .0
+                .iter()
+                .zip(background.0)
+                .all(|(channel, background)| channel.abs_diff(background) <= BACKGROUND_TOLERANCE)
+    };
+
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if is_background(pixel) {
+            continue;
+        }
+        let (min_x, min_y, max_x, max_y) = bounds.get_or_insert((x, y, x, y));
+        (*min_x, *min_y) = ((*min_x).min(x), (*min_y).min(y));
+        (*max_x, *max_y) = ((*max_x).max(x), (*max_y).max(y));
+    }
+
+    let Some((min_x, min_y, max_x, max_y)) = bounds else {
+        return Ok(image.to_vec());
+    };
+
+    let (width, height) = (max_x - min_x + 1, max_y - min_y + 1);
+    let mut cropped = RgbaImage::from_pixel(width + 2 * margin, height + 2 * margin, background);
+    imageops::replace(
+        &mut cropped,
+        &*imageops::crop_imm(&rgba, min_x, min_y, width, height),
+        margin.into(),
+        margin.into(),
+    );
+
+    let cropped = match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(cropped).to_rgb8()),
+        _ => DynamicImage::ImageRgba8(cropped),
+    };
+
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    cropped
+        .write_to(&mut encoded, format)
+        .context("encoding cropped image")?;
+
+    Ok(encoded.into_inner())
+}
+
+/// Re-encode an exported image as lossy WebP, which is much smaller than PNG for
+/// dense notes while staying visually lossless at high quality.
+pub fn to_webp(image: &[u8], quality: f32) -> anyhow::Result<Vec<u8>> {
+    let rgba = image::load_from_memory(image)
+        .context("decoding exported image")?
+        .to_rgba8();
+
+    let encoded =
+        webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height()).encode(quality);
+
+    Ok(encoded.to_vec())
+}
@@ -0,0 +1,96 @@
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Gitignore-style file listing the source paths a batch should skip
+pub const IGNORE_FILE: &str = ".rnote-llm-ignore";
+
+/// Rules from every ignore file of the source tree, each applying to the
+/// directory containing it.
+pub struct IgnoreRules {
+    /// Sorted deepest first, so nested files override their parents
+    rules: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    pub fn new(ignore_files: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut rules = ignore_files
+            .iter()
+            .map(|file| {
+                let mut builder = GitignoreBuilder::new(file.parent().unwrap_or(Path::new("")));
+
+                if let Some(err) = builder.add(file) {
+                    return Err(err).with_context(|| format!("parsing {}", file.display()));
+                }
+
+                builder
+                    .build()
+                    .with_context(|| format!("parsing {}", file.display()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        rules.sort_by_key(|rule| Reverse(rule.path().components().count()));
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_ignored(&self, file: &Path) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| file.starts_with(rule.path()))
+            .map(|rule| rule.matched_path_or_any_parents(file, false))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
+
+/// The --include and --exclude globs of a batch, matched against paths relative
+/// to its source folder
+pub struct PathFilter {
+    /// `None` includes everything
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        let include = match include {
+            [] => None,
+            globs => Some(glob_set(globs)?),
+        };
+
+        Ok(Self {
+            include,
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// Excludes win over includes
+    pub fn is_selected(&self, relative_file: &Path) -> bool {
+        !self.exclude.is_match(relative_file)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(relative_file))
+    }
+}
+
+fn glob_set(globs: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for glob in globs {
+        // `*` stays within a directory, `**` crosses them
+        let glob = GlobBuilder::new(glob)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("parsing glob {glob}"))?;
+        builder.add(glob);
+    }
+
+    builder.build().context("building glob set")
+}
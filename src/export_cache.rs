@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{cli::ExportSettings, mime_type, stable_hash::KeyHasher};
+
+/// Exports kept across runs with `--reuse-export`, under the temporary directory
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join("rnote-llm-exports")
+}
+
+/// Identifies the export of `input_file` with `settings`, changing whenever the
+/// note, anything affecting its image, or the user's `salt` does.
+///
+/// The prompt and model are left out on purpose: they don't change the image,
+/// and reusing it across prompt edits is the point of the cache.
+pub async fn key(
+    input_file: &Path,
+    settings: &ExportSettings,
+    salt: Option<&str>,
+) -> anyhow::Result<String> {
+    let note = tokio::fs::read(input_file)
+        .await
+        .context("opening rnote file for byte read")?;
+
+    let background_color = settings.background_color.map(|rgba| {
+        rgba.iter()
+            .flat_map(|channel| channel.to_bits().to_le_bytes())
+            .collect::<Vec<_>>()
+    });
+
+    let mut hasher = KeyHasher::new();
+    hasher
+        .field(&note)
+        .field(settings.prefs.bitmap_scalefactor.to_bits().to_le_bytes())
+        .field(mime_type(settings.prefs.export_format))
+        .field([
+            u8::from(settings.prefs.with_background),
+            u8::from(settings.prefs.with_pattern),
+            u8::from(settings.first_page_only),
+            u8::from(settings.include_trashed),
+        ])
+        .optional_field(background_color)
+        .optional_field(salt);
+
+    Ok(hasher.finish())
+}
+
+/// The cached export for `key`, if a previous run stored one
+pub async fn load(key: &str) -> Option<Vec<u8>> {
+    tokio::fs::read(cache_dir().join(key)).await.ok()
+}
+
+pub async fn store(key: &str, image: &[u8]) -> std::io::Result<()> {
+    let dir = cache_dir();
+    tokio::fs::create_dir_all(&dir).await?;
+    tokio::fs::write(dir.join(key), image).await
+}
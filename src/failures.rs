@@ -0,0 +1,141 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{backend::BadStatus, repetition::Looping, retry::TimedOut, structured::InvalidJson};
+
+/// Name of the file, placed in the destination folder, listing the jobs that
+/// failed during the last run.
+pub const FAILURE_LOG: &str = "failures.txt";
+
+/// Write the failed `(input, output)` pairs to `log`, one tab separated pair
+/// per line. A run without failures removes any stale log.
+pub fn write(log: &Path, failed: &[(PathBuf, PathBuf)]) -> anyhow::Result<()> {
+    if failed.is_empty() {
+        return match std::fs::remove_file(log) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).context("removing stale failure log")
+            }
+            _ => Ok(()),
+        };
+    }
+
+    let contents: String = failed
+        .iter()
+        .map(|(input, output)| format!("{}\t{}\n", input.display(), output.display()))
+        .collect();
+
+    std::fs::write(log, contents).context("writing failure log")
+}
+
+/// Read back the `(input, output)` pairs written by [`write`].
+pub fn read(log: &Path) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let contents = std::fs::read_to_string(log)
+        .with_context(|| format!("reading failure log {}", log.display()))?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (input, output) = line
+                .split_once('\t')
+                .with_context(|| format!("malformed failure log line: {line}"))?;
+
+            Ok((PathBuf::from(input), PathBuf::from(output)))
+        })
+        .collect()
+}
+
+/// Broad cause of a failed job, for the breakdown printed at the end of a run.
+///
+/// The stages of a job attach [`Category::Export`] and [`Category::Write`] as
+/// context to their errors, the others are told apart by the client error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Export,
+    Api,
+    RateLimit,
+    Network,
+    Timeout,
+    InvalidResponse,
+    Write,
+    Other,
+}
+
+impl Category {
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(category) = err.downcast_ref::<Category>() {
+            return *category;
+        }
+        if err.is::<Looping>() || err.is::<InvalidJson>() {
+            return Category::InvalidResponse;
+        }
+        if err.is::<TimedOut>() {
+            return Category::Timeout;
+        }
+        match err.downcast_ref::<BadStatus>() {
+            Some(BadStatus { code: 429, .. }) => return Category::RateLimit,
+            Some(_) => return Category::Api,
+            None => {}
+        }
+        if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+            return if err.is_decode() {
+                Category::InvalidResponse
+            } else if err.is_timeout() {
+                Category::Timeout
+            } else {
+                Category::Network
+            };
+        }
+
+        match err.downcast_ref::<gemini_rust::ClientError>() {
+            Some(gemini_rust::ClientError::BadResponse { code: 429, .. }) => Category::RateLimit,
+            Some(gemini_rust::ClientError::BadResponse { .. }) => Category::Api,
+            Some(gemini_rust::ClientError::PerformRequest { .. }) => Category::Network,
+            Some(_) => Category::InvalidResponse,
+            None => Category::Other,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Export => "export",
+            Category::Api => "API",
+            Category::RateLimit => "rate limit",
+            Category::Network => "network",
+            Category::Timeout => "timeout",
+            Category::InvalidResponse => "invalid response",
+            Category::Write => "write",
+            Category::Other => "other",
+        }
+    }
+}
+
+impl serde::Serialize for Category {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.label())
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Export => f.write_str("exporting the note failed"),
+            Category::Write => f.write_str("writing the output failed"),
+            _ => write!(f, "{} error", self.label()),
+        }
+    }
+}
+
+/// `"3 export, 1 rate limit"`, for the summary of a run
+pub fn breakdown(categories: &BTreeMap<Category, usize>) -> String {
+    categories
+        .iter()
+        .map(|(category, count)| format!("{count} {}", category.label()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use chrono::{SecondsFormat, Utc};
+
+/// Whether `markdown` already starts with a front matter block, e.g. one the
+/// model wrote itself
+pub fn is_present(markdown: &str) -> bool {
+    end(markdown).is_some()
+}
+
+/// Byte offset just past the line closing the front matter `markdown` starts with,
+/// if it has any. Front matter opens on the very first line and both of its
+/// delimiters are exactly `---`, anything else is a thematic break.
+pub fn end(markdown: &str) -> Option<usize> {
+    let is_delimiter = |line: &str| line.trim_end_matches(['\r', '\n']) == "---";
+
+    let mut lines = markdown.split_inclusive('\n');
+    let opening = lines.next()?;
+    if !is_delimiter(opening) {
+        return None;
+    }
+
+    let mut offset = opening.len();
+    for line in lines {
+        offset += line.len();
+        if is_delimiter(line) {
+            return Some(offset);
+        }
+    }
+
+    None
+}
+
+/// YAML block recording where `markdown` came from, for querying notes in tools
+/// like Obsidian
+pub fn prepend(markdown: &str, source: &Path, model: &str, prompt: &str) -> String {
+    // JSON strings are valid YAML scalars, whatever characters the paths contain
+    let quote = |value: &str| serde_json::to_string(value).expect("strings always serialize");
+
+    format!(
+        "---\nsource: {}\nconverted: {}\nmodel: {}\nprompt: {}\n---\n\n{markdown}",
+        quote(&source.to_string_lossy()),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        quote(model),
+        quote(prompt),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter_ends_after_its_closing_line() {
+        let markdown = "---\ntitle: a\n---\n# Body\n";
+
+        assert_eq!(end(markdown), Some("---\ntitle: a\n---\n".len()));
+        assert!(is_present(markdown));
+    }
+
+    #[test]
+    fn closing_line_may_end_the_document() {
+        let markdown = "---\r\ntitle: a\r\n---";
+
+        assert_eq!(end(markdown), Some(markdown.len()));
+    }
+
+    #[test]
+    fn front_matter_must_open_the_document() {
+        for markdown in ["\n---\ntitle: a\n---\n", "# Page 1\n---\n# Page 2\n---\n"] {
+            assert_eq!(end(markdown), None, "{markdown:?}");
+            assert!(!is_present(markdown), "{markdown:?}");
+        }
+    }
+
+    #[test]
+    fn other_rules_are_thematic_breaks() {
+        for markdown in [
+            "----\ntext\n----\n",
+            "--- \ntext\n---\n",
+            "***\ntext\n***\n",
+        ] {
+            assert_eq!(end(markdown), None, "{markdown:?}");
+            assert!(!is_present(markdown), "{markdown:?}");
+        }
+    }
+
+    #[test]
+    fn only_an_exact_line_closes_it() {
+        let markdown = "---\ntitle: a\n----\nmore: b\n---\n# Body\n";
+
+        assert_eq!(end(markdown), Some(markdown.len() - "# Body\n".len()));
+        assert_eq!(end("---\ntitle: a\n...\n"), None);
+    }
+
+    #[test]
+    fn unclosed_front_matter_is_none() {
+        assert_eq!(end("---\ntitle: a\n"), None);
+        assert!(!is_present("---\n"));
+    }
+
+    #[test]
+    fn prepended_front_matter_is_found() {
+        let markdown = prepend("# Body\n", Path::new("a.rnote"), "model", "prompt");
+
+        assert!(
+            markdown[end(&markdown).unwrap()..]
+                .trim_start()
+                .starts_with("# Body")
+        );
+    }
+}
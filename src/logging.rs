@@ -0,0 +1,82 @@
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::Context;
+use indicatif::MultiProgress;
+use log::{LevelFilter, Log};
+
+/// Install the logger: info and up by default, debug with `-v` and trace with
+/// `-vv`, refined by RUST_LOG. With `log_file` the log is appended to it too.
+///
+/// Log lines are written with the progress bars of `multi` cleared, which then
+/// redraw below them instead of getting torn.
+pub fn init(verbosity: u8, log_file: Option<&Path>, multi: &MultiProgress) -> anyhow::Result<()> {
+    let level = match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let mut builder = env_logger::builder();
+    builder.filter_level(level).parse_default_env();
+
+    if let Some(log_file) = log_file {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .with_context(|| format!("opening log file {}", log_file.display()))?;
+
+        builder.target(env_logger::Target::Pipe(Box::new(Tee(file))));
+    }
+
+    let logger = builder.build();
+    let max_level = logger.filter();
+
+    log::set_boxed_logger(Box::new(SuspendingLogger {
+        logger,
+        multi: multi.clone(),
+    }))
+    .context("installing the logger")?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+/// Hides the progress bars while a line is logged
+struct SuspendingLogger {
+    logger: env_logger::Logger,
+    multi: MultiProgress,
+}
+
+impl Log for SuspendingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.logger.matches(record) {
+            self.multi.suspend(|| self.logger.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+/// Writes the log to stderr and to the --log-file
+struct Tee(File);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::stderr().write_all(buf)?;
+        self.0.write_all(buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()?;
+        self.0.flush()
+    }
+}
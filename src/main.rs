@@ -1,90 +1,639 @@
+mod append;
+mod backend;
+mod budget;
+mod capabilities;
 mod cli;
+mod config;
+mod conversion_cache;
+mod encode;
+mod exclude;
+mod export_cache;
+mod failures;
+mod front_matter;
+mod logging;
+mod markdown;
+mod memory;
+mod passes;
+mod paths;
+mod progress;
+mod prune;
+mod rate_limit;
+mod repetition;
+mod report;
+mod retry;
+mod review;
+#[cfg(feature = "server")]
+mod server;
+mod shard;
+mod sink;
+mod stable_hash;
+mod stats;
+mod structured;
+mod throughput;
 
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
     fs::ReadDir,
+    io::IsTerminal,
     path::{Path, PathBuf},
-    time::Duration,
+    pin::pin,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use base64::{Engine as _, prelude::BASE64_STANDARD};
-use clap::Parser as _;
-use futures::{StreamExt, TryStreamExt};
-use gemini_rust::Gemini;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::LevelFilter;
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
 use rnote_engine::{
     Engine,
-    engine::{
-        EngineSnapshot,
-        export::{SelectionExportFormat, SelectionExportPrefs},
-    },
+    engine::{EngineSnapshot, export::SelectionExportFormat},
 };
 use smol::Executor;
 
-use crate::cli::{Command, Options};
+use crate::{
+    backend::NoteConverter,
+    budget::RequestBudget,
+    capabilities::Capabilities,
+    cli::{Command, ExportSettings, Options},
+    conversion_cache::ConversionCache,
+    exclude::{IgnoreRules, PathFilter},
+    failures::Category,
+    memory::MemoryBudget,
+    paths::OutputNaming,
+    progress::{ProgressFile, ProgressReporter},
+    rate_limit::RateLimiter,
+    repetition::Looping,
+    report::Report,
+    retry::Backoff,
+    sink::{DiskFull, FileSink, OutputSink},
+    stats::{NoteStats, Timings},
+    throughput::Throughput,
+};
+
+/// MIME type of the image produced by exporting with `format`
+fn mime_type(format: SelectionExportFormat) -> &'static str {
+    match format {
+        SelectionExportFormat::Svg => "image/svg+xml",
+        SelectionExportFormat::Png => "image/png",
+        SelectionExportFormat::Jpeg => "image/jpeg",
+    }
+}
+
+/// The engine failing to start, which no retry fixes
+#[derive(Debug)]
+struct EngineInitFailed(String);
+
+impl fmt::Display for EngineInitFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to initialize rnote engine: {}", self.0)
+    }
+}
+
+impl std::error::Error for EngineInitFailed {}
+
+/// A note that can't be exported however often it's tried, like one that isn't
+/// a note at all or has nothing on it
+#[derive(Debug)]
+struct Unexportable(String);
+
+impl fmt::Display for Unexportable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Unexportable {}
+
+/// Only transient failures are worth exporting again: the engine not starting,
+/// notes the engine can't make anything of and files that can't be read stay so.
+fn is_retryable_export(err: &anyhow::Error) -> bool {
+    let unreadable = err.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            )
+        })
+    });
+
+    !unreadable && !err.is::<EngineInitFailed>() && !err.is::<Unexportable>()
+}
 
+/// `Engine::default()` panics rather than failing when its rendering backends can't
+/// start, e.g. with system libraries missing from a container, so turn that into an
+/// error naming the engine instead of a panic deep inside the export.
+fn new_engine() -> anyhow::Result<Engine> {
+    std::panic::catch_unwind(Engine::default).map_err(|panic| {
+        let reason = match panic.downcast::<String>() {
+            Ok(message) => *message,
+            Err(panic) => match panic.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_owned(),
+            },
+        };
+
+        EngineInitFailed(reason).into()
+    })
+}
+
+/// Export the note as an image according to `settings`, returning its bytes along
+/// with their MIME type
 async fn export_rnote_file(
     engine: &mut Engine,
     input_file: impl AsRef<Path>,
-) -> anyhow::Result<Vec<u8>> {
+    settings: ExportSettings,
+) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    let input_file = input_file.as_ref();
+    let read = std::fs::read(input_file).context("opening rnote file for byte read")?;
+
+    export_rnote_bytes(engine, read, input_file.display(), settings).await
+}
+
+/// [`export_rnote_file`] for a note already in memory, called `name` in errors
+async fn export_rnote_bytes(
+    engine: &mut Engine,
+    read: Vec<u8>,
+    name: impl fmt::Display,
+    settings: ExportSettings,
+) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    load_rnote_bytes(engine, read, &name, &settings).await?;
+    export_loaded_note(engine, &name, settings).await
+}
+
+/// Load the note into `engine`, applying the settings that change the document itself
+async fn load_rnote_bytes(
+    engine: &mut Engine,
+    read: Vec<u8>,
+    name: &impl fmt::Display,
+    settings: &ExportSettings,
+) -> anyhow::Result<()> {
     static EXECUTOR: Executor = Executor::new();
 
     let task = async move {
-        let read = std::fs::read(&input_file).context("opening rnote file for byte read")?;
+        ensure_rnote_container(&read, name)?;
 
+        let hint = load_failure_hint(&read);
         let snapshot = EngineSnapshot::load_from_rnote_bytes(read)
             .await
-            .context("loading file into snapshot context")?;
+            .with_context(|| format!("loading {name} into snapshot context, {hint}"))?;
 
+        // these only report which widgets need a redraw, which is irrelevant here.
+        // A failed load shows up as nothing to select instead.
         let _ = engine.load_snapshot(snapshot);
+
+        if let Some([r, g, b, a]) = settings.background_color {
+            let color = &mut engine.document.config.background.color;
+            (color.r, color.g, color.b, color.a) = (r, g, b, a);
+        }
+
+        if settings.include_trashed {
+            let keys = engine.store.stroke_keys_unordered();
+            engine.store.set_trashed_keys(&keys, false);
+        }
+
+        Ok(())
+    };
+
+    EXECUTOR.run(task).await
+}
+
+/// Select the strokes of the note loaded in `engine` that `settings` exports
+fn select_exported_strokes(
+    engine: &mut Engine,
+    name: &impl fmt::Display,
+    settings: &ExportSettings,
+) -> anyhow::Result<()> {
+    if settings.first_page_only {
+        let first_page = engine
+            .document
+            .pages_bounds()
+            .into_iter()
+            .next()
+            .with_context(|| Unexportable(format!("{name} has no pages")))?;
+        let keys = engine
+            .store
+            .stroke_keys_as_rendered_intersecting_bounds(first_page);
+        engine.store.set_selected_keys(&keys, true);
+    } else {
         let _ = engine.select_all_strokes();
+    }
 
-        let export_prefs = SelectionExportPrefs {
-            with_background: true,
-            with_pattern: false,
-            optimize_printing: false,
-            export_format: SelectionExportFormat::Png,
-            ..Default::default()
+    anyhow::ensure!(
+        !engine.store.selection_keys_unordered().is_empty(),
+        Unexportable(format!("{name} has no strokes to export"))
+    );
+
+    Ok(())
+}
+
+/// Export the strokes of the note loaded in `engine` that `settings` selects
+async fn export_loaded_note(
+    engine: &mut Engine,
+    name: &impl fmt::Display,
+    settings: ExportSettings,
+) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    static EXECUTOR: Executor = Executor::new();
+
+    let task = async move {
+        select_exported_strokes(engine, name, &settings)?;
+
+        let mime = mime_type(settings.prefs.export_format);
+        let export = export_selected_strokes(engine, name, &settings).await?;
+
+        Ok((export, mime))
+    };
+
+    EXECUTOR.run(task).await
+}
+
+/// Export every page of the note loaded in `engine` on its own, leaving out the
+/// blank ones. Strokes crossing a page break end up on both pages.
+async fn export_loaded_pages(
+    engine: &mut Engine,
+    name: &impl fmt::Display,
+    settings: ExportSettings,
+) -> anyhow::Result<(Vec<Vec<u8>>, &'static str)> {
+    static EXECUTOR: Executor = Executor::new();
+
+    let task = async move {
+        let page_count = if settings.first_page_only {
+            1
+        } else {
+            usize::MAX
         };
+        let mut pages = Vec::new();
 
-        let export = engine
-            .export_selection(Some(export_prefs))
+        for page in engine.document.pages_bounds().into_iter().take(page_count) {
+            let selected = engine.store.selection_keys_unordered();
+            engine.store.set_selected_keys(&selected, false);
+
+            let keys = engine
+                .store
+                .stroke_keys_as_rendered_intersecting_bounds(page);
+            if keys.is_empty() {
+                continue;
+            }
+            engine.store.set_selected_keys(&keys, true);
+
+            pages.push(export_selected_strokes(engine, name, &settings).await?);
+        }
+
+        anyhow::ensure!(
+            !pages.is_empty(),
+            Unexportable(format!("{name} has no strokes to export"))
+        );
+        log::debug!("exported {name} as {} pages", pages.len());
+
+        Ok((pages, mime_type(settings.prefs.export_format)))
+    };
+
+    EXECUTOR.run(task).await
+}
+
+/// Export the strokes currently selected in `engine` as a single image
+async fn export_selected_strokes(
+    engine: &mut Engine,
+    name: &impl fmt::Display,
+    settings: &ExportSettings,
+) -> anyhow::Result<Vec<u8>> {
+    let export = engine
+        .export_selection(Some(settings.prefs))
+        .await
+        .with_context(|| format!("exporting {name}: the export task was cancelled"))?
+        .with_context(|| format!("exporting {name}"))?
+        .with_context(|| format!("exporting {name}: nothing was selected"))?;
+
+    if let Ok((width, height)) = image::ImageReader::new(std::io::Cursor::new(&export))
+        .with_guessed_format()
+        .map_err(image::ImageError::from)
+        .and_then(|reader| reader.into_dimensions())
+    {
+        log::info!("exported {name} at {width}x{height} pixels");
+    }
+
+    Ok(export)
+}
+
+/// Fail early on files that can't be notes, whose engine errors would be confusing.
+///
+/// Notes are gzip compressed, newer rnote versions prefix them with their own
+/// magic instead; anything else isn't worth handing to the engine.
+fn ensure_rnote_container(note: &[u8], name: &impl fmt::Display) -> anyhow::Result<()> {
+    const RNOTE_MAGICS: [&[u8]; 2] = [b"\x1f\x8b", b"RNOTE"];
+
+    anyhow::ensure!(
+        RNOTE_MAGICS.iter().any(|magic| note.starts_with(magic)),
+        Unexportable(format!(
+            "{name} is not a valid .rnote file, {}",
+            load_failure_hint(note)
+        ))
+    );
+
+    Ok(())
+}
+
+/// Likely reason for `note` not loading, since the engine's own errors say little
+/// about files that aren't notes at all
+fn load_failure_hint(note: &[u8]) -> &'static str {
+    const FOREIGN_FORMATS: [(&[u8], &str); 4] = [
+        (b"%PDF", "it is a PDF document"),
+        (b"\x89PNG", "it is a PNG image"),
+        (b"\xff\xd8\xff", "it is a JPEG image"),
+        (b"PK\x03\x04", "it is a zip archive"),
+    ];
+
+    if note.is_empty() {
+        return "the file is empty";
+    }
+
+    FOREIGN_FORMATS
+        .iter()
+        .find(|(magic, _)| note.starts_with(magic))
+        .map_or(
+            "it appears encrypted, corrupt or from an unsupported rnote version",
+            |(_, hint)| *hint,
+        )
+}
+
+/// Check that a note loads, without paying for exporting it
+async fn validate_rnote_file(input_file: &Path) -> anyhow::Result<()> {
+    static EXECUTOR: Executor = Executor::new();
+
+    let task = async move {
+        let read = std::fs::read(input_file).context("opening rnote file for byte read")?;
+        ensure_rnote_container(&read, &input_file.display())?;
+
+        let hint = load_failure_hint(&read);
+        EngineSnapshot::load_from_rnote_bytes(read)
             .await
-            .unwrap()
-            .unwrap()
-            .unwrap();
+            .with_context(|| format!("loading file into snapshot context, {hint}"))?;
 
-        Ok(export)
+        Ok(())
     };
 
     EXECUTOR.run(task).await
 }
 
+/// Validate every input before the first request, so a corrupt note can't stop a
+/// batch that was already mostly paid for. Invalid notes either fail the whole
+/// run or, with `skip_invalid`, are dropped with a warning.
+async fn preflight(jobs: Vec<Job>, skip_invalid: bool) -> anyhow::Result<Vec<Job>> {
+    let mut valid = Vec::with_capacity(jobs.len());
+    let mut invalid = vec![];
+
+    for job in jobs {
+        match validate_rnote_file(&job.input_file).await {
+            Ok(()) => valid.push(job),
+            Err(err) => {
+                log::warn!("{} is not a valid note: {err:#}", job.input_file.display());
+                invalid.push(job.input_file);
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        skip_invalid || invalid.is_empty(),
+        "{} invalid notes found during preflight, pass --skip-invalid to convert the rest anyway",
+        invalid.len()
+    );
+
+    Ok(valid)
+}
+
+/// Export the note `read` gives, called `name` in errors, one image per page with
+/// `--per-page`, along with its stats when `--stats-sidecar` wants them and the
+/// time it took to load and export
+async fn export_with_retries(
+    name: &str,
+    read: impl Fn() -> anyhow::Result<Vec<u8>> + Clone + Send + 'static,
+    options: &Options,
+    settings: &ExportSettings,
+) -> anyhow::Result<((Vec<Vec<u8>>, &'static str), Option<NoteStats>, Timings)> {
+    retry::EXPORT_BACKOFF
+        .retry(
+            || {
+                let name = name.to_owned();
+                let read = read.clone();
+                let settings = settings.clone();
+                let with_stats = options.stats_sidecar;
+
+                // exports are CPU bound, so each gets a thread of its own instead of
+                // queueing up on the runtime's single one
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        smol::block_on(export_on_new_engine(&name, read, settings, with_stats))
+                    })
+                    .await?
+                }
+            },
+            is_retryable_export,
+            |attempt, _, err| {
+                log::warn!(
+                    "exporting {name} failed, retrying ({}/{}): {err:#}",
+                    attempt + 1,
+                    retry::EXPORT_BACKOFF.attempts
+                )
+            },
+        )
+        .await
+}
+
+/// Export the note `read` gives with an engine of its own, gathering the stats of
+/// the exported strokes too when `with_stats` is set
+async fn export_on_new_engine(
+    name: &str,
+    read: impl FnOnce() -> anyhow::Result<Vec<u8>>,
+    settings: ExportSettings,
+    with_stats: bool,
+) -> anyhow::Result<((Vec<Vec<u8>>, &'static str), Option<NoteStats>, Timings)> {
+    let mut engine = new_engine()?;
+    let mut timings = Timings::default();
+
+    let start = Instant::now();
+    let read = read()?;
+    load_rnote_bytes(&mut engine, read, &name, &settings).await?;
+    timings.load_ms = Timings::since(start);
+
+    let start = Instant::now();
+    let per_page = settings.per_page;
+    let export = if per_page {
+        export_loaded_pages(&mut engine, &name, settings.clone()).await?
+    } else {
+        let (image, mime) = export_loaded_note(&mut engine, &name, settings.clone()).await?;
+        (vec![image], mime)
+    };
+    timings.export_ms = Timings::since(start);
+
+    // the exported strokes are still selected, unless only the last page is
+    if with_stats && per_page {
+        select_exported_strokes(&mut engine, &name, &settings)?;
+    }
+    let stats = with_stats.then(|| NoteStats::of_selection(&engine));
+
+    Ok((export, stats, timings))
+}
+
+/// Apply --autocrop and --webp to the exported `images` of type `mime`, off the
+/// runtime's thread like the export itself
+async fn process_images(
+    mut images: Vec<Vec<u8>>,
+    mut mime: &'static str,
+    options: &Options,
+) -> anyhow::Result<(Vec<Vec<u8>>, &'static str)> {
+    if options.autocrop {
+        images = tokio::task::spawn_blocking(move || {
+            images
+                .iter()
+                .map(|image| encode::autocrop(image, encode::AUTOCROP_MARGIN))
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .await??;
+    }
+
+    // Gemini accepts WebP input alongside PNG and JPEG
+    if options.webp {
+        let quality = options.webp_quality;
+        images = tokio::task::spawn_blocking(move || {
+            images
+                .iter()
+                .map(|image| encode::to_webp(image, quality))
+                .collect::<anyhow::Result<Vec<_>>>()
+        })
+        .await??;
+        mime = encode::WEBP_MIME;
+    }
+
+    Ok((images, mime))
+}
+
+/// Log the problems --validate-md finds in `markdown`, called `name`, repairing
+/// them with --fix-md. Also tells whether there were any.
+fn check_markdown(markdown: String, name: impl fmt::Display, options: &Options) -> (String, bool) {
+    if !options.validate_md && !options.fix_md {
+        return (markdown, false);
+    }
+
+    let analysis = markdown::analyze(&markdown);
+    for issue in &analysis.issues {
+        log::warn!("{name}: {issue}");
+    }
+
+    let found = !analysis.issues.is_empty();
+    if options.fix_md {
+        (analysis.fixed, found)
+    } else {
+        (markdown, found)
+    }
+}
+
+/// Told to the model when a note is sent as one image per page, see --per-page
+const PAGES_INSTRUCTION: &str = "\n\nThe note is attached as one image per page, in page order. \
+    Transcribe them in that order, separating the pages with a horizontal rule (`---`) \
+    on a line of its own.";
+
 async fn convert_note(
-    client: &Gemini,
+    client: &dyn NoteConverter,
     system_prompt: impl Into<String>,
-    note_png: &[u8],
+    note_images: &[Vec<u8>],
+    mime: &str,
+    thinking_budget: Option<i32>,
+    options: &Options,
 ) -> anyhow::Result<String> {
-    let encoded = BASE64_STANDARD.encode(note_png);
+    let mut system_prompt = system_prompt.into();
+    if note_images.len() > 1 {
+        system_prompt.push_str(PAGES_INSTRUCTION);
+    }
+
+    let request = client.convert(system_prompt, note_images, mime, thinking_budget, options);
+    let timeout = Duration::from_secs(options.timeout_secs);
+    let text = tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| retry::TimedOut(timeout))??;
+
+    if let Some(looping) = repetition::find_loop(&text) {
+        if options.fail_on_loop {
+            return Err(looping.into());
+        }
+        log::warn!("{looping}");
+    }
 
-    let output = client
-        .generate_content()
-        .with_dynamic_thinking()
-        .with_system_instruction(system_prompt)
-        .with_inline_data(encoded, "image/png")
-        .execute()
-        .await?;
+    if options.output_json {
+        return Ok(structured::parse(&text)?);
+    }
 
-    Ok(output.text())
+    Ok(text)
 }
 
-async fn execute_job(
-    gemini_client: &Gemini,
-    system_prompt: impl Into<String>,
-    skip_existing: bool,
-    job: Job,
-) -> anyhow::Result<()> {
+const PROMPT_SIDECAR_EXTENSION: &str = "prompt";
+
+/// `<name>.rnote.prompt` next to the input file, overriding the system prompt for it alone
+fn prompt_sidecar(input_file: &Path) -> PathBuf {
+    let mut sidecar = input_file.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(PROMPT_SIDECAR_EXTENSION);
+    sidecar.into()
+}
+
+/// Marks the files written with `--annotate`, telling them apart from hand-written notes
+const GENERATOR_MARKER: &str = "<!-- generated by rnote-llm -->";
+
+/// How a job that didn't fail ended
+enum Outcome {
+    Converted,
+    Skipped,
+    OverBudget,
+}
+
+/// State shared by all the jobs of a run
+struct RunContext<'a, S = FileSink> {
+    client: &'a dyn NoteConverter,
+    /// Client for --fallback-model
+    fallback_client: Option<Box<dyn NoteConverter>>,
+    options: &'a Options,
+    system_prompt: Cow<'static, str>,
+    /// --language and reference material appended to every system prompt
+    prompt_context: String,
+    memory: Option<MemoryBudget>,
+    budget: Option<RequestBudget>,
+    /// Pacing of the requests for --rpm
+    rate_limiter: Option<RateLimiter>,
+    backoff: Backoff,
+    reporters: Vec<Box<dyn ProgressReporter>>,
+    sink: S,
+    /// Outputs in which --validate-md found problems
+    markdown_issues: AtomicUsize,
+    /// Review every conversion before writing it, one job at a time
+    interactive: bool,
+    /// Root of the notes, which --front-matter names them relative to
+    source_folder: Option<PathBuf>,
+    /// Conversions reused across runs with --cache-dir
+    conversion_cache: Option<ConversionCache>,
+    /// File receiving every conversion, with `batch --merge-into`
+    merge_into: Option<PathBuf>,
+    /// Sections held back for --concat-order and --merge-into, as `(source, markdown)`
+    sections: Mutex<Vec<(PathBuf, String)>>,
+}
+
+/// Whether `output_file` was written after `input_file` last changed. Either
+/// modification time being unreadable counts as stale, to regenerate to be safe.
+async fn is_up_to_date(input_file: &Path, output_file: &Path) -> bool {
+    let modified = async |path: &Path| tokio::fs::metadata(path).await?.modified();
+
+    match (modified(input_file).await, modified(output_file).await) {
+        (Ok(input), Ok(output)) => output >= input,
+        _ => false,
+    }
+}
+
+async fn execute_job<S: OutputSink>(run: &RunContext<'_, S>, job: &Job) -> anyhow::Result<Outcome> {
+    let options = run.options;
+
     let build_message = |stage: &str| {
         format!(
             "({} -> {}) {}",
@@ -101,41 +650,468 @@ async fn execute_job(
     job.progress_bar
         .set_style(ProgressStyle::with_template("[{elapsed_precise}] {spinner} {msg}").unwrap());
 
-    if skip_existing && tokio::fs::try_exists(&job.output_file).await? {
+    let skip_reason = if options.force {
+        None
+    } else if options.skip_existing && tokio::fs::try_exists(&job.output_file).await? {
+        Some("exists")
+    } else if options.skip_unchanged && is_up_to_date(&job.input_file, &job.output_file).await {
+        Some("up to date")
+    } else {
+        None
+    };
+
+    if options.dry_run {
+        let note = match skip_reason {
+            Some(reason) => format!(" (skipped, {reason})"),
+            None => String::new(),
+        };
+        job.progress_bar.suspend(|| {
+            println!(
+                "{} -> {}{note}",
+                job.input_file.display(),
+                job.output_file.display()
+            )
+        });
+        job.progress_bar.finish_and_clear();
+
+        return Ok(Outcome::Skipped);
+    }
+
+    if let Some(reason) = skip_reason {
+        job.progress_bar
+            .finish_with_message(build_message(&format!("Skipping, {reason}...")));
+
+        return Ok(Outcome::Skipped);
+    }
+
+    if options.annotate {
+        // the suffix of --gzip is already on the output path
+        let existing = tokio::fs::read(&job.output_file)
+            .await
+            .and_then(|existing| sink::decode(&job.output_file, &existing));
+        match existing {
+            Ok(existing) if !existing.contains(GENERATOR_MARKER) => {
+                if !options.force {
+                    log::warn!(
+                        "{} was not generated by rnote-llm, skipping it (use --force to overwrite)",
+                        job.output_file.display()
+                    );
+                    job.progress_bar
+                        .finish_with_message(build_message("Skipping non-generated file..."));
+
+                    return Ok(Outcome::Skipped);
+                }
+
+                log::warn!(
+                    "overwriting {} which was not generated by rnote-llm",
+                    job.output_file.display()
+                );
+            }
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).context("reading existing output file");
+            }
+            _ => {}
+        }
+    }
+
+    // every request is charged below, this only keeps the jobs starting after the
+    // budget ran out from paying for their export
+    if run.budget.as_ref().is_some_and(RequestBudget::is_exhausted) {
         job.progress_bar
-            .finish_with_message(build_message("Skipping existing..."));
+            .finish_with_message(build_message("Budget exceeded, skipped"));
 
-        return Ok(());
+        return Ok(Outcome::OverBudget);
     }
 
     /*
      * Export RNote
      */
-    job.progress_bar
-        .set_message(build_message("Exporting RNote file..."));
+    if let Some(memory) = &run.memory {
+        job.progress_bar
+            .set_message(build_message("Waiting for memory budget..."));
+        memory.wait_for_room().await;
+    }
 
-    let mut engine = Engine::default();
-    let note_png = export_rnote_file(&mut engine, &job.input_file).await?;
+    let sidecar = prompt_sidecar(&job.input_file);
+    let (mut system_prompt, mut prompt_label) = match tokio::fs::read_to_string(&sidecar).await {
+        Ok(sidecar_prompt) => (
+            cli::normalize_prompt(&sidecar_prompt),
+            sidecar.display().to_string(),
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            (run.system_prompt.to_string(), options.prompt_label())
+        }
+        Err(err) => return Err(err).context("reading prompt sidecar"),
+    };
+    system_prompt.push_str(&run.prompt_context);
 
-    /*
-     * Convert to Markdown
-     */
-    job.progress_bar
-        .set_message(build_message("Converting to Markdown..."));
+    // --adaptive-dpi starts at half the resolution, going up only when needed
+    let full_resolution = options.export_settings();
+    let mut settings = if options.adaptive_dpi {
+        options.export_settings_scaled(options.scale() / 2.0)
+    } else {
+        full_resolution.clone()
+    };
+    let mut escalated = !options.adaptive_dpi;
+    // summed over every export and pass of the job
+    let mut timings = Timings::default();
+
+    let (mut converted, stats, model) = loop {
+        let cache_key = if options.reuse_export {
+            Some(
+                export_cache::key(&job.input_file, &settings, options.cache_salt.as_deref())
+                    .await?,
+            )
+        } else {
+            None
+        };
+        let cached = match &cache_key {
+            Some(key) => export_cache::load(key).await,
+            None => None,
+        };
+
+        let ((note_images, export_mime), stats) = match cached {
+            Some(image) => {
+                log::debug!("reusing the cached export of {}", job.input_file.display());
+                let mime = mime_type(settings.prefs.export_format);
+                ((vec![image], mime), None)
+            }
+            None => {
+                job.progress_bar
+                    .set_message(build_message("Exporting RNote file..."));
+
+                let input_file = job.input_file.clone();
+                let read =
+                    move || std::fs::read(&input_file).context("opening rnote file for byte read");
+                let ((images, mime), stats, export_timings) = export_with_retries(
+                    &job.input_file.display().to_string(),
+                    read,
+                    options,
+                    &settings,
+                )
+                .await
+                .context(Category::Export)?;
+                timings.load_ms += export_timings.load_ms;
+                timings.export_ms += export_timings.export_ms;
+
+                // --per-page doesn't go with --reuse-export, there's a single image
+                if let Some(key) = &cache_key
+                    && let Err(err) = export_cache::store(key, &images[0]).await
+                {
+                    log::warn!(
+                        "caching the export of {} failed: {err}",
+                        job.input_file.display()
+                    );
+                }
+
+                ((images, mime), stats)
+            }
+        };
+
+        let start = Instant::now();
+        let (note_images, export_mime) = process_images(note_images, export_mime, options)
+            .await
+            .context(Category::Export)?;
+        timings.export_ms += Timings::since(start);
+
+        let mime = options.image_mime.as_deref().unwrap_or(export_mime);
+
+        for reporter in &run.reporters {
+            for image in &note_images {
+                reporter.exported(&job.input_file, image, mime);
+            }
+        }
+
+        // held as long as the image
+        let _reservation = match &run.memory {
+            Some(memory) => Some(memory.reserve(note_images.iter().map(Vec::len).sum()).await),
+            None => None,
+        };
+
+        /*
+         * Convert to Markdown
+         */
+        job.progress_bar
+            .set_message(build_message("Converting to Markdown..."));
+
+        let mut client_override = None;
+        let mut thinking = options.thinking_budget();
+        let mut fallen_back = false;
+        let fallback_thinking = options.thinking_budget_for(
+            options
+                .fallback_model
+                .is_some_and(|model| matches!(model, cli::Model::Gemini25Flash)),
+        );
 
-    let converted = convert_note(gemini_client, system_prompt, &note_png).await?;
-    tokio::fs::create_dir_all(job.output_file.parent().unwrap()).await?;
-    tokio::fs::write(&job.output_file, converted).await?;
+        // a reviewed conversion is always fresh
+        let conversion_cache = run.conversion_cache.as_ref().filter(|_| !run.interactive);
+        let conversion_key = conversion_cache
+            .map(|_| ConversionCache::key(&note_images, mime, &system_prompt, options));
+        let mut cached = match (conversion_cache, &conversion_key) {
+            (Some(cache), Some(key)) => cache.load(key).await,
+            _ => None,
+        };
+        let cache_hit = cached.is_some();
+
+        let converted = loop {
+            if let Some(markdown) = cached.take() {
+                log::debug!(
+                    "reusing the cached conversion of {}",
+                    job.input_file.display()
+                );
+                break markdown;
+            }
+
+            let client = client_override.as_deref().unwrap_or(run.client);
+
+            let mut transcriptions = Vec::with_capacity(options.passes as usize);
+            for pass in 1..=options.passes {
+                if options.passes > 1 {
+                    job.progress_bar.set_message(build_message(&format!(
+                        "Converting to Markdown (pass {pass}/{})...",
+                        options.passes
+                    )));
+                }
+
+                let start = Instant::now();
+                let transcription = loop {
+                    let (client, thinking) = match &run.fallback_client {
+                        Some(fallback) if fallen_back => (fallback.as_ref(), fallback_thinking),
+                        _ => (client, thinking),
+                    };
+
+                    let (system_prompt, note_images) = (system_prompt.as_str(), &note_images);
+                    let result = run
+                        .backoff
+                        .retry(
+                            // retries count against --rpm and --max-requests too
+                            || async move {
+                                if run.budget.as_ref().is_some_and(|budget| !budget.try_take()) {
+                                    return Err(budget::Exhausted.into());
+                                }
+                                if let Some(limiter) = &run.rate_limiter {
+                                    let start = limiter.reserve();
+                                    if start > Instant::now() {
+                                        let message = job.progress_bar.message();
+                                        job.progress_bar
+                                            .set_message(build_message("Rate limited, waiting..."));
+                                        tokio::time::sleep_until(start.into()).await;
+                                        job.progress_bar.set_message(message);
+                                    }
+                                }
+
+                                convert_note(
+                                    client,
+                                    system_prompt,
+                                    note_images,
+                                    mime,
+                                    thinking,
+                                    options,
+                                )
+                                .await
+                            },
+                            // a looping model usually recovers on a fresh attempt
+                            |err| retry::is_transient(err) || err.is::<Looping>(),
+                            |attempt, delay, _| {
+                                job.progress_bar.set_message(build_message(&format!(
+                                    "Request failed, retrying in {:.1}s ({}/{})...",
+                                    delay.as_secs_f64(),
+                                    attempt + 1,
+                                    run.backoff.attempts
+                                )))
+                            },
+                        )
+                        .await;
+
+                    match result {
+                        Ok(transcription) => break transcription,
+                        Err(err) if err.is::<budget::Exhausted>() => {
+                            job.progress_bar
+                                .finish_with_message(build_message("Budget exceeded, skipped"));
+
+                            return Ok(Outcome::OverBudget);
+                        }
+                        // a model picked during review is used as is
+                        Err(err)
+                            if run.fallback_client.is_some()
+                                && !fallen_back
+                                && client_override.is_none()
+                                && !retry::is_auth_error(&err) =>
+                        {
+                            log::warn!(
+                                "{} failed with the primary model, falling back: {err:#}",
+                                job.input_file.display()
+                            );
+                            fallen_back = true;
+                        }
+                        Err(err) => {
+                            return Err(
+                                err.context(format!("converting {}", job.input_file.display()))
+                            );
+                        }
+                    }
+                };
+                timings.api_ms += Timings::since(start);
+                transcriptions.push(transcription);
+            }
+
+            let converted = passes::pick_best(transcriptions).context("no conversion pass ran")?;
+            if !run.interactive {
+                break converted;
+            }
+
+            let decision = job.progress_bar.suspend(|| {
+                review::ask(
+                    std::io::stdin().lock(),
+                    std::io::stdout(),
+                    &job.input_file.display().to_string(),
+                    &converted,
+                )
+            })?;
+
+            match decision {
+                review::Decision::Accept => break converted,
+                review::Decision::Reject => {
+                    job.progress_bar
+                        .finish_with_message(build_message("Rejected, skipping..."));
+                    return Ok(Outcome::Skipped);
+                }
+                review::Decision::RetryWithPrompt(prompt) => {
+                    system_prompt = format!("{}{}", prompt.text(), run.prompt_context);
+                    prompt_label = prompt.to_string();
+                }
+                review::Decision::RetryWithModel(model) => {
+                    thinking =
+                        options.thinking_budget_for(matches!(model, cli::Model::Gemini25Flash));
+                    client_override = Some(backend::converter(options, Some(model))?);
+                }
+            }
+
+            job.progress_bar
+                .set_message(build_message("Converting to Markdown..."));
+        };
+
+        // only what the requested model produced belongs under its key
+        if let (Some(cache), Some(key)) = (conversion_cache, &conversion_key)
+            && !cache_hit
+            && !fallen_back
+            && client_override.is_none()
+            && let Err(err) = cache.store(key, &converted).await
+        {
+            log::warn!(
+                "caching the conversion of {} failed: {err}",
+                job.input_file.display()
+            );
+        }
+
+        // a looping or very short transcription usually means the model couldn't read
+        // the image, while a reviewed one was already judged
+        let struggled = converted.trim().chars().count() < options.adaptive_min_len
+            || repetition::find_loop(&converted).is_some();
+        if escalated || run.interactive || !struggled {
+            let model = match options.fallback_model {
+                Some(fallback) if fallen_back => {
+                    log::info!("{} was converted by {fallback}", job.output_file.display());
+                    fallback.to_string()
+                }
+                _ => options.model_id(),
+            };
+            break (converted, stats, model);
+        }
+
+        log::info!(
+            "{} looks unreadable at low resolution, retrying at full resolution",
+            job.input_file.display()
+        );
+        settings = full_resolution.clone();
+        escalated = true;
+    };
+
+    let (checked, found_issues) = check_markdown(converted, job.output_file.display(), options);
+    if found_issues {
+        run.markdown_issues.fetch_add(1, Ordering::Relaxed);
+    }
+    converted = checked;
+
+    let own_front_matter = front_matter::is_present(&converted);
+
+    // front matter the model wrote has to stay on top to count as such
+    if options.annotate {
+        converted = match front_matter::end(&converted) {
+            Some(end) => {
+                let (front_matter, body) = converted.split_at(end);
+                format!(
+                    "{}\n{GENERATOR_MARKER}\n{body}",
+                    front_matter.trim_end_matches(['\r', '\n'])
+                )
+            }
+            None => format!("{GENERATOR_MARKER}\n{converted}"),
+        };
+    }
+
+    // last, since front matter has to open the file
+    if options.front_matter && !own_front_matter {
+        let source = match &run.source_folder {
+            Some(folder) => job
+                .input_file
+                .strip_prefix(folder)
+                .unwrap_or(&job.input_file),
+            None => &job.input_file,
+        };
+        converted = front_matter::prepend(&converted, source, &model, &prompt_label);
+    }
+    let converted = options.final_newline.apply(converted);
+
+    if options.concat_order.is_some() || run.merge_into.is_some() {
+        run.sections
+            .lock()
+            .unwrap()
+            .push((job.input_file.clone(), converted));
+
+        job.progress_bar
+            .finish_with_message(build_message("Converted, appending at the end"));
+        return Ok(Outcome::Converted);
+    }
+
+    if let Some(target) = options.append_to.clone() {
+        let source = job.input_file.clone();
+        tokio::task::spawn_blocking(move || append::append_section(&target, &source, &converted))
+            .await?
+            .map_err(sink::write_error)
+            .context(Category::Write)?;
+
+        job.progress_bar
+            .finish_with_message(build_message("Appended!"));
+        return Ok(Outcome::Converted);
+    }
+
+    let start = Instant::now();
+    run.sink
+        .write(&job.output_file, converted)
+        .await
+        .context(Category::Write)?;
+    timings.write_ms = Timings::since(start);
+
+    if let Some(mut stats) = stats {
+        stats.timings = timings;
+        tokio::fs::write(
+            stats::sidecar(&job.output_file),
+            serde_json::to_vec_pretty(&stats)?,
+        )
+        .await
+        .map_err(sink::write_error)
+        .context(Category::Write)?;
+    }
 
     job.progress_bar.finish_with_message(build_message("Done!"));
-    Ok(())
+    Ok(Outcome::Converted)
 }
 
 /// Recursively search directories for files
 struct DirWalker {
-    /// 0 -> top level directory
+    /// 1 -> top level directory
     ///
-    /// 1 -> top level -> subdirectory
+    /// 2 -> top level -> subdirectory
     ///
     /// 3 -> top level -> sub -> sub-sub
     max_depth: usize,
@@ -161,16 +1137,21 @@ impl Iterator for DirWalker {
         let explore = self.path_stack.last_mut()?;
         let next = explore.next();
 
+        // an unreadable entry or subfolder shouldn't abort a walk over hundreds of notes
         match next {
-            Some(Ok(file)) if file.file_type().unwrap().is_file() => return Some(file.path()),
-            Some(Ok(file))
-                if file.file_type().unwrap().is_dir()
-                    && self.path_stack.len() <= self.max_depth =>
-            {
-                let readdir = std::fs::read_dir(file.path()).unwrap();
-                self.path_stack.push(readdir);
-            }
-            _ => {
+            Some(Ok(entry)) => match entry.file_type() {
+                Ok(file_type) if file_type.is_file() => return Some(entry.path()),
+                Ok(file_type) if file_type.is_dir() && self.path_stack.len() < self.max_depth => {
+                    match std::fs::read_dir(entry.path()) {
+                        Ok(readdir) => self.path_stack.push(readdir),
+                        Err(err) => log::warn!("skipping {}: {err}", entry.path().display()),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => log::warn!("skipping {}: {err}", entry.path().display()),
+            },
+            Some(Err(err)) => log::warn!("skipping an unreadable directory entry: {err}"),
+            None => {
                 self.path_stack.pop();
             }
         }
@@ -179,6 +1160,55 @@ impl Iterator for DirWalker {
     }
 }
 
+/// Fail when several notes would be converted to the same output, as --flatten or
+/// notes differing only by extension can cause, listing them
+fn ensure_distinct_outputs(jobs: &[Job]) -> anyhow::Result<()> {
+    let mut inputs: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+    for job in jobs {
+        inputs
+            .entry(&job.output_file)
+            .or_default()
+            .push(&job.input_file);
+    }
+
+    let collisions: Vec<String> = inputs
+        .into_iter()
+        .filter(|(_, inputs)| inputs.len() > 1)
+        .map(|(output, inputs)| {
+            let inputs: Vec<_> = inputs
+                .iter()
+                .map(|input| input.display().to_string())
+                .collect();
+            format!("{} <- {}", output.display(), inputs.join(", "))
+        })
+        .collect();
+
+    anyhow::ensure!(
+        collisions.is_empty(),
+        "several notes would be written to the same output:\n{}",
+        collisions.join("\n")
+    );
+
+    Ok(())
+}
+
+/// Create `output_folder` and resolve it to its canonical form. A dry run creates
+/// nothing, making a missing folder absolute instead.
+fn prepare_output_folder(output_folder: &Path, dry_run: bool) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !output_folder.exists() || output_folder.is_dir(),
+        "{} exists and is not a directory",
+        output_folder.display()
+    );
+
+    if dry_run && !output_folder.exists() {
+        return Ok(std::path::absolute(output_folder)?);
+    }
+
+    std::fs::create_dir_all(output_folder).context("creating output folder")?;
+    Ok(output_folder.canonicalize()?)
+}
+
 struct Job {
     progress_bar: ProgressBar,
     input_file: PathBuf,
@@ -202,27 +1232,69 @@ impl Job {
         input_folder: &Path,
         output_folder: &Path,
         max_depth: usize,
+        extensions: &[String],
+        filter: &PathFilter,
+        naming: &OutputNaming,
+        dry_run: bool,
     ) -> anyhow::Result<Vec<Job>> {
         // let readdir = std::fs::read_dir(input_folder)?;
-        std::fs::create_dir(output_folder).ok();
+        // canonical, so walked files share input_folder's exact prefix whatever
+        // trailing slashes or `.`/`..` components it was given with, and on Windows
+        // both come back in the \\?\ form
+        let output_folder =
+            prepare_output_folder(&paths::extended_length(output_folder)?, dry_run)?;
         let input_folder = input_folder.canonicalize()?;
-        let output_folder = output_folder.canonicalize()?;
 
-        let readdir = DirWalker::new(&input_folder, max_depth)?;
+        let (ignore_files, files): (Vec<_>, Vec<_>) = DirWalker::new(&input_folder, max_depth)?
+            .partition(|file| {
+                file.file_name()
+                    .is_some_and(|name| name == exclude::IGNORE_FILE)
+            });
+        let ignore_rules = IgnoreRules::new(&ignore_files)?;
 
         let mut jobs = vec![];
-        let multi = MultiProgress::new();
-        let start_components = input_folder.components().count();
 
-        for file in readdir {
+        for file in files {
+            // prompt sidecars belong to the note next to them
+            if file
+                .extension()
+                .is_some_and(|ext| ext == PROMPT_SIDECAR_EXTENSION)
+            {
+                continue;
+            }
+
+            let wanted = file.extension().is_some_and(|ext| {
+                extensions
+                    .iter()
+                    .any(|wanted| ext.eq_ignore_ascii_case(wanted.trim_start_matches('.')))
+            });
+            if !wanted {
+                log::debug!("{} is not a note, skipping it", file.display());
+                continue;
+            }
+
+            if ignore_rules.is_ignored(&file) {
+                log::debug!("{} is ignored, skipping it", file.display());
+                continue;
+            }
+
             // generate relative path in respect to input_folder
-            let relative_file: PathBuf = file.components().skip(start_components).collect();
-            let mut output_file = output_folder.join(relative_file);
+            let relative_file = file.strip_prefix(&input_folder).with_context(|| {
+                format!(
+                    "{} is not inside {}",
+                    file.display(),
+                    input_folder.display()
+                )
+            })?;
+            if !filter.is_selected(relative_file) {
+                log::debug!("{} is filtered out, skipping it", file.display());
+                continue;
+            }
 
-            output_file.set_extension("md");
+            let output_file = naming.place(&output_folder, relative_file);
 
             // create progress bar
-            let pb = multi.add(ProgressBar::new_spinner());
+            let pb = ProgressBar::new_spinner();
             let job = Job::new(pb, file, output_file);
 
             jobs.push(job);
@@ -230,45 +1302,571 @@ impl Job {
 
         Ok(jobs)
     }
+
+    /// Jobs for the notes listed in `list`, mirrored from `input_folder` into
+    /// `output_folder` like [`Job::from_folder`] does. Relative entries are
+    /// resolved against `input_folder`.
+    fn from_list(
+        list: &Path,
+        input_folder: &Path,
+        output_folder: &Path,
+        naming: &OutputNaming,
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<Job>> {
+        let contents = std::fs::read_to_string(list)
+            .with_context(|| format!("reading input list {}", list.display()))?;
+
+        let output_folder = prepare_output_folder(output_folder, dry_run)?;
+        let input_folder = input_folder.canonicalize()?;
+
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let file = input_folder
+                    .join(line)
+                    .canonicalize()
+                    .with_context(|| format!("resolving listed note {line}"))?;
+                let relative_file = file.strip_prefix(&input_folder).with_context(|| {
+                    format!("listed note {line} is outside {}", input_folder.display())
+                })?;
+                let output_file = naming.place(&output_folder, relative_file);
+
+                let pb = ProgressBar::new_spinner();
+                Ok(Job::new(pb, file, output_file))
+            })
+            .collect()
+    }
+
+    fn from_files(
+        files: &[PathBuf],
+        output_folder: &Path,
+        naming: &OutputNaming,
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<Job>> {
+        let output_folder = &paths::extended_length(output_folder)?;
+        if !dry_run {
+            std::fs::create_dir_all(output_folder).context("creating output folder")?;
+        }
+
+        files
+            .iter()
+            .map(|file| {
+                anyhow::ensure!(file.is_file(), "{} is not a file", file.display());
+
+                let file_name = file.file_name().context("input file has no name")?;
+                let output_file = naming.output_of(&output_folder.join(file_name));
+
+                let pb = ProgressBar::new_spinner();
+                Ok(Job::new(pb, file, output_file))
+            })
+            .collect()
+    }
+
+    /// Rebuild the jobs recorded in the failure log of a previous batch
+    fn from_failures(destination_folder: &Path) -> anyhow::Result<Vec<Job>> {
+        let log = destination_folder.join(failures::FAILURE_LOG);
+        anyhow::ensure!(
+            log.is_file(),
+            "no {} found in {}",
+            failures::FAILURE_LOG,
+            destination_folder.display()
+        );
+
+        let jobs = failures::read(&log)?
+            .into_iter()
+            .map(|(input_file, output_file)| {
+                let pb = ProgressBar::new_spinner();
+                Job::new(pb, input_file, output_file)
+            })
+            .collect();
+
+        Ok(jobs)
+    }
+
+    /// Jobs reconverting the `sources` of the outputs shorter than `min_len`
+    /// characters in `output_folder`
+    fn from_short_outputs(
+        sources: &prune::Sources,
+        output_folder: &Path,
+        min_len: usize,
+    ) -> anyhow::Result<Vec<Job>> {
+        let output_folder = output_folder.canonicalize()?;
+
+        let mut jobs = vec![];
+
+        for output_file in DirWalker::new(&output_folder, usize::MAX)? {
+            let relative_file = output_file.strip_prefix(&output_folder)?;
+            if !sources.is_output(relative_file) {
+                continue;
+            }
+
+            let contents = std::fs::read(&output_file)
+                .and_then(|contents| sink::decode(&output_file, &contents))
+                .with_context(|| format!("reading {}", output_file.display()))?;
+            // only the transcription counts, not what was added around it
+            let contents = contents.replacen(GENERATOR_MARKER, "", 1);
+            let transcription = match front_matter::end(&contents) {
+                Some(end) => &contents[end..],
+                None => &contents,
+            };
+            if transcription.trim().chars().count() >= min_len {
+                continue;
+            }
+
+            let Some(input_file) = sources.note_of(relative_file) else {
+                log::warn!(
+                    "{} is short but its source doesn't exist, skipping it",
+                    output_file.display()
+                );
+                continue;
+            };
+
+            let pb = ProgressBar::new_spinner();
+            jobs.push(Job::new(pb, input_file, output_file));
+        }
+
+        Ok(jobs)
+    }
+}
+
+/// Give `output_file` the `.gz` suffix of --gzip, unless it's the standard output.
+/// Jobs rebuilt from a previous gzip run already carry it.
+fn add_gzip_suffix(output_file: &mut PathBuf) {
+    if output_file.extension().is_none_or(|ext| ext != "gz")
+        && *output_file != Path::new(sink::STDOUT)
+    {
+        output_file.as_mut_os_string().push(".gz");
+    }
+}
+
+/// Write the image that would be sent to the model, without converting it
+async fn render_note(
+    input_file: &Path,
+    output_file: &Path,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let mut engine = new_engine()?;
+    let (image, mime) =
+        export_rnote_file(&mut engine, input_file, options.export_settings()).await?;
+
+    // processed the same way as the images of a conversion
+    let (mut images, _) = process_images(vec![image], mime, options).await?;
+
+    tokio::fs::write(output_file, images.remove(0))
+        .await
+        .context("writing rendered image")
 }
 
 async fn run() -> anyhow::Result<()> {
-    env_logger::builder()
-        .filter_level(LevelFilter::Info)
-        .parse_default_env()
-        .init();
+    let cmdline = config::parse()?;
 
-    let cmdline = Options::parse();
-    let model = cmdline.model.to_gemini_model();
-    let prompt = cmdline.prompt()?;
+    // created first, so log lines can clear the progress bars
+    let multi = MultiProgress::new();
+    logging::init(cmdline.verbose, cmdline.log_file.as_deref(), &multi)?;
 
-    let gemini = Gemini::with_model(cmdline.key, model)?;
+    if let Some(tmp_dir) = &cmdline.tmp_dir {
+        std::fs::create_dir_all(tmp_dir).context("creating temporary directory")?;
+
+        // the engine and its rendering backends pick up their scratch space from the
+        // environment, so that's where the directory has to go
+        for var in ["TMPDIR", "TMP", "TEMP"] {
+            // SAFETY: no other thread exists yet, the runtime is single threaded and
+            // its blocking pool only starts with the first blocking task
+            unsafe { std::env::set_var(var, tmp_dir) };
+        }
+    }
+
+    if let Command::Capabilities { json } = cmdline.command {
+        return Capabilities::of_this_build().print(json);
+    }
 
-    let jobs = match cmdline.command {
+    if let Command::Prune {
+        dry_run,
+        extension,
+        flatten,
+        source_folder,
+        destination_folder,
+    } = &cmdline.command
+    {
+        let sources =
+            prune::Sources::new(source_folder, &cmdline.output_naming(), extension, *flatten)?;
+        let orphans = prune::orphans(&sources, destination_folder)?;
+        return prune::remove(&orphans, *dry_run || cmdline.dry_run);
+    }
+
+    // rendering never talks to the model, so it doesn't need a key
+    if let Command::Render { file, output_file } = &cmdline.command {
+        return render_note(file, output_file, &cmdline).await;
+    }
+
+    let system_prompt = cmdline.prompt()?;
+    // every prompt gets these, including sidecar and server ones
+    let prompt_context = cmdline.language_directive() + &cmdline.prompt_context()?;
+
+    let client: Box<dyn NoteConverter> = if cmdline.dry_run {
+        Box::new(backend::DryRun)
+    } else {
+        backend::converter(&cmdline, None)?
+    };
+    #[cfg(feature = "server")]
+    if let Command::Serve { port } = cmdline.command {
+        let server = server::Server::new(cmdline, system_prompt.into_owned(), prompt_context);
+        return server.serve(port).await;
+    }
+
+    let fallback_client = cmdline
+        .fallback_model
+        .filter(|_| !cmdline.dry_run)
+        .map(|fallback| backend::converter(&cmdline, Some(fallback)))
+        .transpose()?;
+
+    let interactive = cmdline.interactive && std::io::stdout().is_terminal();
+    if cmdline.interactive && !interactive {
+        log::info!("stdout isn't a terminal, ignoring --interactive");
+    }
+
+    let naming = cmdline.output_naming();
+    let (mut jobs, failure_log) = match &cmdline.command {
         Command::Batch {
             source_folder,
             destination_folder,
             max_depth,
-        } => Job::from_folder(&source_folder, &destination_folder, max_depth)?,
+            from_file,
+            extension,
+            include,
+            exclude,
+            flatten,
+            merge_into: _,
+        } => {
+            let naming = OutputNaming {
+                flatten: *flatten,
+                ..naming
+            };
+            let jobs = match from_file {
+                Some(list) => Job::from_list(
+                    list,
+                    source_folder,
+                    destination_folder,
+                    &naming,
+                    cmdline.dry_run,
+                )?,
+                None => Job::from_folder(
+                    source_folder,
+                    destination_folder,
+                    *max_depth,
+                    extension,
+                    &PathFilter::new(include, exclude)?,
+                    &naming,
+                    cmdline.dry_run,
+                )?,
+            };
+            ensure_distinct_outputs(&jobs)?;
+            (jobs, Some(destination_folder.join(failures::FAILURE_LOG)))
+        }
         Command::Single { file, output_file } => {
-            let output_file = output_file.unwrap_or_else(|| file.with_extension("md"));
-            vec![Job::new(ProgressBar::new_spinner(), &file, &output_file)]
+            let output_file = output_file
+                .clone()
+                .unwrap_or_else(|| naming.output_of(file));
+            let jobs = vec![Job::new(ProgressBar::new_spinner(), file, output_file)];
+            (jobs, None)
+        }
+        Command::Files { output_dir, files } => {
+            let jobs = Job::from_files(files, output_dir, &naming, cmdline.dry_run)?;
+            ensure_distinct_outputs(&jobs)?;
+            (jobs, Some(output_dir.join(failures::FAILURE_LOG)))
         }
+        Command::Retry { destination_folder } => {
+            let jobs = Job::from_failures(destination_folder)?;
+            (jobs, Some(destination_folder.join(failures::FAILURE_LOG)))
+        }
+        Command::FixShort {
+            min_len,
+            extension,
+            flatten,
+            source_folder,
+            destination_folder,
+        } => {
+            let sources = prune::Sources::new(source_folder, &naming, extension, *flatten)?;
+            let jobs = Job::from_short_outputs(&sources, destination_folder, *min_len)?;
+            (jobs, Some(destination_folder.join(failures::FAILURE_LOG)))
+        }
+        Command::Render { .. } | Command::Capabilities { .. } | Command::Prune { .. } => {
+            unreachable!("only conversions build jobs")
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { .. } => unreachable!("only conversions build jobs"),
     };
 
-    futures::stream::iter(jobs)
-        .map(|job| execute_job(&gemini, prompt.clone(), cmdline.skip_existing, job))
-        .buffer_unordered(10)
-        .try_collect::<Vec<()>>()
-        .await?;
+    if let Some(shard) = cmdline.shard {
+        // outputs are laid out the same way on every host, unlike the inputs
+        jobs = shard.select(jobs, |job| &job.output_file);
+    }
 
-    Ok(())
+    if cmdline.preflight {
+        jobs = preflight(jobs, cmdline.skip_invalid).await?;
+    }
+
+    if cmdline.gzip {
+        for job in &mut jobs {
+            add_gzip_suffix(&mut job.output_file);
+        }
+    }
+
+    // spread big notes living in the same folder across the whole run
+    if cmdline.shuffle {
+        let mut rng = match cmdline.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        jobs.shuffle(&mut rng);
+    }
+
+    let mut reporters: Vec<Box<dyn ProgressReporter>> = vec![];
+    // a dry run leaves every file alone
+    if let Some(path) = cmdline.progress_file.clone()
+        && !cmdline.dry_run
+    {
+        reporters.push(Box::new(ProgressFile::new(path, jobs.len())));
+    }
+
+    // progress shares the terminal with the markdown written to stdout
+    if jobs
+        .iter()
+        .any(|job| job.output_file == Path::new(sink::STDOUT))
+    {
+        multi.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    let total = jobs.len();
+    // added first, so it stays above the spinners of the jobs
+    let overall = multi.add(ProgressBar::new(total as u64));
+    overall.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {wide_bar} {pos}/{len} ({percent}%) {msg}",
+        )
+        .unwrap(),
+    );
+    for job in &jobs {
+        multi.add(job.progress_bar.clone());
+    }
+    let mut throughput = Throughput::new(Instant::now());
+
+    let mut merge_into = match &cmdline.command {
+        Command::Batch { merge_into, .. } => merge_into.clone(),
+        _ => None,
+    };
+    // it goes through the same sink as the outputs of the jobs
+    if cmdline.gzip
+        && let Some(merge_into) = &mut merge_into
+    {
+        add_gzip_suffix(merge_into);
+    }
+    anyhow::ensure!(
+        merge_into.is_none() || cmdline.append_to.is_none(),
+        "--merge-into and --append-to can't be combined"
+    );
+    anyhow::ensure!(
+        merge_into.is_none() || !cmdline.output_json,
+        "--merge-into writes markdown, it can't be combined with --output-json"
+    );
+    let source_folder = match &cmdline.command {
+        Command::Batch { source_folder, .. } | Command::FixShort { source_folder, .. } => {
+            source_folder.canonicalize().ok()
+        }
+        _ => None,
+    };
+
+    let run = RunContext {
+        client: client.as_ref(),
+        fallback_client,
+        options: &cmdline,
+        system_prompt,
+        prompt_context,
+        memory: cmdline.max_memory.map(MemoryBudget::new),
+        budget: cmdline.max_requests.map(RequestBudget::new),
+        rate_limiter: cmdline.rpm.map(RateLimiter::new),
+        backoff: Backoff {
+            base: Duration::from_millis(cmdline.retry_base_ms),
+            max: Duration::from_millis(cmdline.retry_max_ms),
+            attempts: retry::MAX_ATTEMPTS,
+        },
+        reporters,
+        sink: FileSink {
+            gzip: cmdline.gzip,
+            dir_mode: cmdline.dir_mode,
+            file_mode: cmdline.file_mode,
+        },
+        markdown_issues: AtomicUsize::new(0),
+        interactive,
+        sections: Mutex::new(vec![]),
+        merge_into,
+        conversion_cache: cmdline.cache_dir.clone().map(ConversionCache::new),
+        source_folder,
+    };
+    let run = &run;
+
+    let cooldown = Duration::from_millis(cmdline.cooldown_ms);
+
+    let outcomes = futures::stream::iter(jobs.into_iter().enumerate())
+        // jobs are pulled only as slots free up, so this spaces out job starts
+        .then(|(index, job)| async move {
+            if index > 0 && !cooldown.is_zero() {
+                tokio::time::sleep(cooldown).await;
+            }
+            job
+        })
+        .map(|job| async move {
+            for reporter in &run.reporters {
+                reporter.started(&job.input_file);
+            }
+
+            let result = execute_job(run, &job).await;
+
+            for reporter in &run.reporters {
+                reporter.finished(&job.input_file, result.is_ok());
+            }
+
+            (job, result)
+        })
+        // reviews block the thread on stdin, so they come one after the other
+        .buffer_unordered(if interactive { 1 } else { cmdline.concurrency });
+    let mut outcomes = pin!(outcomes);
+
+    let mut report = Report::default();
+    let mut first_error = None;
+    let mut abort = None;
+    let mut consecutive_failures = 0;
+
+    while let Some((job, result)) = outcomes.next().await {
+        overall.inc(1);
+        throughput.record(Instant::now());
+        if let (Some(per_minute), Some(eta)) = (
+            throughput.per_minute(),
+            throughput.eta(total - overall.position() as usize),
+        ) {
+            overall.set_message(format!(
+                "{per_minute:.1} files/min, {} left",
+                indicatif::HumanDuration(eta)
+            ));
+        }
+
+        match result {
+            Ok(outcome) => {
+                consecutive_failures = 0;
+                match outcome {
+                    Outcome::Converted => report.converted += 1,
+                    Outcome::Skipped => report.skipped += 1,
+                    Outcome::OverBudget => report.over_budget += 1,
+                }
+            }
+            Err(err) => {
+                job.progress_bar.abandon_with_message(format!(
+                    "({} -> {}) Failed: {err:#}",
+                    job.input_file.display(),
+                    job.output_file.display()
+                ));
+                report.fail(job.input_file, job.output_file, &err);
+                consecutive_failures += 1;
+
+                if cmdline.fail_fast {
+                    abort = Some(err.context("aborting the run, --fail-fast is set"));
+                    break;
+                }
+
+                if err.downcast_ref::<DiskFull>().is_some() {
+                    abort = Some(err.context("aborting the run, no further output can be written"));
+                    break;
+                }
+
+                // a streak of failures usually means something systemic, like a revoked key
+                if cmdline
+                    .max_consecutive_failures
+                    .is_some_and(|max| consecutive_failures >= max)
+                {
+                    abort = Some(err.context(format!(
+                        "aborting the run after {consecutive_failures} consecutive failures"
+                    )));
+                    break;
+                }
+
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    overall.finish();
+
+    // appended even after an abort, so the conversions that did finish aren't lost
+    if let (Some(order), Some(target)) = (cmdline.concat_order, &cmdline.append_to) {
+        let sections = std::mem::take(&mut *run.sections.lock().unwrap());
+        let target = target.clone();
+
+        tokio::task::spawn_blocking(move || append::append_sorted(&target, sections, order))
+            .await?
+            .map_err(sink::write_error)
+            .context("appending the converted sections")?;
+    }
+
+    if let Some(target) = &run.merge_into {
+        let sections = std::mem::take(&mut *run.sections.lock().unwrap());
+        if !sections.is_empty() {
+            run.sink
+                .write(target, append::merge(sections))
+                .await
+                .context("writing the merged conversions")?;
+        }
+    }
+
+    report.log_summary();
+    if let Some(path) = &cmdline.report
+        && !cmdline.dry_run
+    {
+        report.write(path)?;
+    }
+
+    if report.over_budget > 0 {
+        log::warn!(
+            "{} jobs skipped, the request budget was exceeded",
+            report.over_budget
+        );
+    }
+
+    let markdown_issues = run.markdown_issues.load(Ordering::Relaxed);
+    if markdown_issues > 0 {
+        let action = if cmdline.fix_md { "repaired" } else { "found" };
+        log::warn!("markdown issues {action} in {markdown_issues} outputs");
+    }
+
+    if !report.failed.is_empty() {
+        log::warn!(
+            "{} jobs failed: {}",
+            report.failed.len(),
+            failures::breakdown(&report.categories())
+        );
+    }
+
+    // a dry run leaves the log of the previous run alone
+    if let Some(failure_log) = failure_log.filter(|_| !cmdline.dry_run) {
+        failures::write(&failure_log, &report.failed_pairs())?;
+    }
+
+    match abort.or(first_error) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let err = run().await;
     if let Err(err) = err {
-        log::error!("{err:?}");
+        // failing before the logger is installed, e.g. on a broken config file
+        if log::max_level() == log::LevelFilter::Off {
+            eprintln!("Error: {err:?}");
+        } else {
+            log::error!("{err:?}");
+        }
+        std::process::exit(1);
     }
 }
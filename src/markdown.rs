@@ -0,0 +1,523 @@
+use std::fmt;
+
+/// Structural problem found in the model output
+pub enum Issue {
+    UnclosedFence {
+        line: usize,
+    },
+    TableColumns {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    UnclosedMath {
+        line: usize,
+        display: bool,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::UnclosedFence { line } => {
+                write!(f, "code fence opened on line {line} is never closed")
+            }
+            Issue::TableColumns {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "table row on line {line} has {found} columns instead of {expected}"
+            ),
+            Issue::UnclosedMath { line, display } => {
+                let delimiter = if *display { "$$" } else { "$" };
+                write!(
+                    f,
+                    "math opened with {delimiter} on line {line} is never closed"
+                )
+            }
+        }
+    }
+}
+
+pub struct Analysis {
+    pub issues: Vec<Issue>,
+    /// The markdown with dangling fences closed and the table rows short of the
+    /// header's columns padded, between leading and trailing pipes
+    pub fixed: String,
+}
+
+struct Fence {
+    marker: char,
+    len: usize,
+    line: usize,
+}
+
+/// Check `markdown` for unclosed code fences and ragged tables, repairing them
+/// along the way.
+///
+/// This covers the breakages models actually produce rather than the whole
+/// CommonMark spec.
+pub fn analyze(markdown: &str) -> Analysis {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut issues = vec![];
+    let mut fixed = Vec::with_capacity(lines.len());
+    let mut open_fence: Option<Fence> = None;
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+
+        if let Some(fence) = &open_fence {
+            if closes(fence, line) {
+                open_fence = None;
+            }
+            fixed.push(line.to_owned());
+            index += 1;
+            continue;
+        }
+
+        if let Some((marker, len)) = fence_of(line) {
+            open_fence = Some(Fence {
+                marker,
+                len,
+                line: index + 1,
+            });
+            fixed.push(line.to_owned());
+            index += 1;
+            continue;
+        }
+
+        let starts_table = line.contains('|')
+            && lines
+                .get(index + 1)
+                .is_some_and(|next| next.contains('|') && is_delimiter_row(next));
+        if !starts_table {
+            fixed.push(line.to_owned());
+            index += 1;
+            continue;
+        }
+
+        let expected = cells(line).len();
+        let rows = lines[index..]
+            .iter()
+            .take_while(|row| row.contains('|') && !row.trim().is_empty())
+            .count();
+
+        for (offset, row) in lines[index..index + rows].iter().enumerate() {
+            let mut row_cells = cells(row);
+            if row_cells.len() != expected {
+                issues.push(Issue::TableColumns {
+                    line: index + offset + 1,
+                    expected,
+                    found: row_cells.len(),
+                });
+            }
+
+            // rows with too many cells are left alone, there's no telling which to drop
+            if row_cells.len() < expected {
+                row_cells.resize(expected, "");
+                fixed.push(format!("| {} |", row_cells.join(" | ")));
+            } else {
+                fixed.push((*row).to_owned());
+            }
+        }
+
+        index += rows;
+    }
+
+    if let Some(fence) = open_fence {
+        issues.push(Issue::UnclosedFence { line: fence.line });
+        fixed.push(fence.marker.to_string().repeat(fence.len));
+    }
+
+    check_math(&lines, &mut issues);
+
+    let mut fixed = fixed.join("\n");
+    if markdown.ends_with('\n') {
+        fixed.push('\n');
+    }
+
+    Analysis { issues, fixed }
+}
+
+/// Look for `$` and `$$` delimiters left open outside of code. Inline math is
+/// considered closed at the end of its paragraph at the latest, and a lone `$`
+/// followed by a digit is taken for a price rather than reported.
+fn check_math(lines: &[&str], issues: &mut Vec<Issue>) {
+    let mut open_fence: Option<Fence> = None;
+    // lines on which the current display and inline math were opened, the latter
+    // along with whether it could be a price instead
+    let mut display: Option<usize> = None;
+    let mut inline: Option<(usize, bool)> = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        let number = index + 1;
+
+        if let Some(fence) = &open_fence {
+            if closes(fence, line) {
+                open_fence = None;
+            }
+            continue;
+        }
+        if display.is_none()
+            && let Some((marker, len)) = fence_of(line)
+        {
+            open_fence = Some(Fence {
+                marker,
+                len,
+                line: number,
+            });
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            if let Some((line, false)) = inline.take() {
+                issues.push(Issue::UnclosedMath {
+                    line,
+                    display: false,
+                });
+            }
+            continue;
+        }
+
+        let mut chars = line.chars().peekable();
+        let mut prev = None;
+        let mut in_code = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                // escaped characters, \$ included
+                '\\' => {
+                    chars.next();
+                }
+                '`' if display.is_none() && inline.is_none() => in_code = !in_code,
+                '$' if !in_code => {
+                    if chars.next_if_eq(&'$').is_some() {
+                        display = match display {
+                            Some(_) => None,
+                            None => Some(number),
+                        };
+                    } else if display.is_none() {
+                        let next = chars.peek().copied();
+                        inline = match inline {
+                            Some(_) if closes_math(prev, next) => None,
+                            None if opens_math(next) => {
+                                Some((number, next.is_some_and(|next| next.is_ascii_digit())))
+                            }
+                            open => open,
+                        };
+                    }
+                }
+                _ => {}
+            }
+            prev = Some(c);
+        }
+    }
+
+    if let Some(line) = display {
+        issues.push(Issue::UnclosedMath {
+            line,
+            display: true,
+        });
+    }
+    if let Some((line, false)) = inline {
+        issues.push(Issue::UnclosedMath {
+            line,
+            display: false,
+        });
+    }
+}
+
+/// Marker and length of the code fence opened or closed by `line`, if any
+fn fence_of(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start();
+    // four spaces of indentation make an indented code block instead
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = trimmed.chars().take_while(|&c| c == marker).count();
+
+    (len >= 3).then_some((marker, len))
+}
+
+fn closes(fence: &Fence, line: &str) -> bool {
+    fence_of(line).is_some_and(|(marker, len)| {
+        // closing fences carry no info string
+        marker == fence.marker
+            && len >= fence.len
+            && line.trim().trim_start_matches(marker).is_empty()
+    })
+}
+
+/// Whether a `$` followed by `next` can open inline math: `$ x$` isn't math
+fn opens_math(next: Option<char>) -> bool {
+    next.is_some_and(|next| !next.is_whitespace())
+}
+
+/// Whether a `$` between `prev` and `next` can close inline math, which rules out
+/// the second price of `$5 and $10`
+fn closes_math(prev: Option<char>, next: Option<char>) -> bool {
+    prev.is_some_and(|prev| !prev.is_whitespace())
+        && !next.is_some_and(|next| next.is_ascii_digit())
+}
+
+/// The cells of a table row. Escaped pipes and the ones inside code spans or
+/// inline math, like `$|x|$`, don't separate cells.
+fn cells(row: &str) -> Vec<&str> {
+    let row = row.trim();
+    let chars: Vec<(usize, char)> = row.char_indices().collect();
+    let char_at = |index: usize| chars.get(index).map(|(_, c)| *c);
+
+    let mut separators = vec![];
+    // pipes inside the span opened last, separators after all if it never closes
+    let mut pending = vec![];
+    let mut code: Option<usize> = None;
+    let mut math: Option<usize> = None;
+    let mut index = 0;
+
+    while let Some((offset, c)) = chars.get(index).copied() {
+        match c {
+            '\\' if code.is_none() => index += 1,
+            '`' if math.is_none() => {
+                let run = chars[index..].iter().take_while(|(_, c)| *c == '`').count();
+                code = match code {
+                    None => Some(run),
+                    Some(open) if open == run => {
+                        pending.clear();
+                        None
+                    }
+                    open => open,
+                };
+                index += run - 1;
+            }
+            '$' if code.is_none() => {
+                let run = chars[index..].iter().take_while(|(_, c)| *c == '$').count();
+                let (prev, next) = (index.checked_sub(1).and_then(char_at), char_at(index + run));
+                // `$$` delimits display math whatever surrounds it
+                math = match math {
+                    Some(open) if open == run && (run > 1 || closes_math(prev, next)) => {
+                        pending.clear();
+                        None
+                    }
+                    None if run > 1 || opens_math(next) => Some(run),
+                    open => open,
+                };
+                index += run - 1;
+            }
+            '|' if code.is_some() || math.is_some() => pending.push(offset),
+            '|' => separators.push(offset),
+            _ => {}
+        }
+        index += 1;
+    }
+    separators.append(&mut pending);
+    separators.sort_unstable();
+
+    // the leading and trailing pipes only frame the row
+    let mut start = 0;
+    let mut cells = vec![];
+    for separator in separators {
+        if separator > 0 {
+            cells.push(&row[start..separator]);
+        }
+        start = separator + 1;
+    }
+    if start < row.len() || cells.is_empty() {
+        cells.push(&row[start..]);
+    }
+
+    cells.into_iter().map(str::trim).collect()
+}
+
+fn is_delimiter_row(row: &str) -> bool {
+    cells(row).iter().all(|cell| {
+        let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+        !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unclosed_fences(analysis: &Analysis) -> Vec<usize> {
+        analysis
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::UnclosedFence { line } => Some(*line),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn closed_fence_is_left_alone() {
+        let markdown = "text\n```rust\nfn main() {}\n```\n";
+        let analysis = analyze(markdown);
+
+        assert!(analysis.issues.is_empty());
+        assert_eq!(analysis.fixed, markdown);
+    }
+
+    #[test]
+    fn dangling_fence_is_closed() {
+        let analysis = analyze("text\n```rust\nfn main() {}\n");
+
+        assert_eq!(unclosed_fences(&analysis), [2]);
+        assert_eq!(analysis.fixed, "text\n```rust\nfn main() {}\n```\n");
+    }
+
+    #[test]
+    fn fence_is_closed_with_its_own_marker_and_length() {
+        let analysis = analyze("~~~~\ncode\n```");
+
+        assert_eq!(unclosed_fences(&analysis), [1]);
+        assert_eq!(analysis.fixed, "~~~~\ncode\n```\n~~~~");
+    }
+
+    #[test]
+    fn shorter_fence_does_not_close() {
+        let analysis = analyze("````\ncode\n```\n");
+
+        assert_eq!(unclosed_fences(&analysis), [1]);
+        assert_eq!(analysis.fixed, "````\ncode\n```\n````\n");
+    }
+
+    #[test]
+    fn fence_with_info_string_does_not_close() {
+        let analysis = analyze("```\ncode\n```rust\n");
+
+        assert_eq!(unclosed_fences(&analysis), [1]);
+        assert_eq!(analysis.fixed, "```\ncode\n```rust\n```\n");
+    }
+
+    #[test]
+    fn indented_backticks_are_not_a_fence() {
+        let markdown = "    ```\n    code\n";
+        let analysis = analyze(markdown);
+
+        assert!(analysis.issues.is_empty());
+        assert_eq!(analysis.fixed, markdown);
+    }
+
+    #[test]
+    fn tables_inside_fences_are_not_repaired() {
+        let markdown = "```\n| a | b |\n|---|---|\n| c |\n```\n";
+        let analysis = analyze(markdown);
+
+        assert!(analysis.issues.is_empty());
+        assert_eq!(analysis.fixed, markdown);
+    }
+
+    #[test]
+    fn ragged_table_rows_are_padded() {
+        let analysis = analyze("| a | b |\n|---|---|\n| c |\n");
+
+        assert!(matches!(
+            analysis.issues[..],
+            [Issue::TableColumns {
+                line: 3,
+                expected: 2,
+                found: 1,
+            }]
+        ));
+        // only the short row is rewritten
+        assert_eq!(analysis.fixed, "| a | b |\n|---|---|\n| c |  |\n");
+    }
+
+    fn assert_table_untouched(markdown: &str) {
+        let analysis = analyze(markdown);
+
+        assert!(analysis.issues.is_empty(), "{markdown}");
+        assert_eq!(analysis.fixed, markdown);
+    }
+
+    #[test]
+    fn escaped_pipes_do_not_split_cells() {
+        assert_table_untouched("| op | meaning |\n|---|---|\n| a \\| b | or |\n");
+    }
+
+    #[test]
+    fn pipes_in_code_spans_do_not_split_cells() {
+        assert_table_untouched(
+            "| cmd | use |\n|---|---|\n| `ls | wc` | count |\n| ``a`|`b`` | ticks |\n",
+        );
+    }
+
+    #[test]
+    fn pipes_in_inline_math_do_not_split_cells() {
+        assert_table_untouched(
+            "| expr | value |\n|---|---|\n| $|x|$ | abs |\n| $$\\|v\\|$$ | norm |\n",
+        );
+    }
+
+    #[test]
+    fn prices_are_not_math() {
+        assert_table_untouched("| item | cost |\n|---|---|\n| $5 | $10 |\n");
+    }
+
+    #[test]
+    fn row_with_too_many_cells_is_reported_but_kept() {
+        let analysis = analyze("| a |\n|---|\n| b | c |\n");
+
+        assert_eq!(analysis.issues.len(), 1);
+        assert_eq!(analysis.fixed, "| a |\n|---|\n| b | c |\n");
+    }
+
+    #[test]
+    fn dollars_inside_fences_are_not_math() {
+        let analysis = analyze("```sh\necho $HOME\n```\n");
+
+        assert!(analysis.issues.is_empty());
+    }
+
+    fn unclosed_math(markdown: &str) -> Vec<(usize, bool)> {
+        analyze(markdown)
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::UnclosedMath { line, display } => Some((*line, *display)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn balanced_math_is_not_reported() {
+        assert!(
+            unclosed_math("Euler: $e^{i\\pi} + 1 = 0$.\n\n$$\n\\int_0^1 x\\,dx\n$$\n").is_empty()
+        );
+    }
+
+    #[test]
+    fn unclosed_inline_math_is_reported_at_its_paragraph() {
+        assert_eq!(
+            unclosed_math("# Notes\n\nwhere $x^2 + y\nis positive\n\nnext paragraph\n"),
+            [(3, false)]
+        );
+    }
+
+    #[test]
+    fn unclosed_display_math_is_reported() {
+        assert_eq!(
+            unclosed_math("text\n\n$$\n\\sum_i a_i\n\nmore text\n"),
+            [(3, true)]
+        );
+    }
+
+    #[test]
+    fn prices_are_not_unclosed_math() {
+        assert!(unclosed_math("The book costs $5 and the course $10.\n").is_empty());
+        assert!(unclosed_math("Only $5 today\n").is_empty());
+    }
+
+    #[test]
+    fn escaped_and_spaced_dollars_are_not_math() {
+        assert!(unclosed_math("a \\$ sign, and $ alone\n").is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Soft limit on the size of the exported images held in memory at once.
+///
+/// Permits are KiB of image data rather than jobs, so a few huge notes can
+/// hold off new exports just like many small ones.
+pub struct MemoryBudget {
+    semaphore: Semaphore,
+    limit_kib: u32,
+}
+
+impl MemoryBudget {
+    pub fn new(limit_mb: u32) -> Self {
+        let limit_kib = limit_mb.saturating_mul(1024);
+
+        Self {
+            semaphore: Semaphore::new(limit_kib as usize),
+            limit_kib,
+        }
+    }
+
+    /// Wait until the budget has room left before starting a new export
+    pub async fn wait_for_room(&self) {
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+    }
+
+    /// Account for an exported image of `bytes` until the returned permit is dropped.
+    ///
+    /// Images larger than the whole budget are clamped to it, so they still get
+    /// to run, just alone.
+    pub async fn reserve(&self, bytes: usize) -> SemaphorePermit<'_> {
+        let kib = bytes.div_ceil(1024).clamp(1, self.limit_kib as usize) as u32;
+
+        self.semaphore
+            .acquire_many(kib)
+            .await
+            .expect("semaphore is never closed")
+    }
+}
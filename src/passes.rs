@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+/// Reconcile the transcriptions of several passes over the same note.
+///
+/// The transcription most passes agree on wins, ignoring surrounding whitespace.
+/// Ties go to the longest one, since passes mostly disagree by dropping content.
+pub fn pick_best(transcriptions: Vec<String>) -> Option<String> {
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    for transcription in &transcriptions {
+        *votes.entry(transcription.trim()).or_default() += 1;
+    }
+
+    let best = transcriptions
+        .iter()
+        .enumerate()
+        .max_by_key(|(index, transcription)| {
+            let trimmed = transcription.trim();
+            // earlier passes win full ties, keeping the pick deterministic
+            (votes[trimmed], trimmed.len(), std::cmp::Reverse(*index))
+        })
+        .map(|(index, _)| index)?;
+
+    transcriptions.into_iter().nth(best)
+}
@@ -0,0 +1,169 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// Make `path` absolute and, on Windows, switch it to the extended-length `\\?\`
+/// form so deeply nested vaults aren't cut off at `MAX_PATH`.
+///
+/// The conversion goes through UTF-16 code units rather than `str`, so any file
+/// name Windows accepts survives it unchanged.
+#[cfg(windows)]
+pub fn extended_length(path: &Path) -> std::io::Result<PathBuf> {
+    use std::{
+        os::windows::ffi::{OsStrExt, OsStringExt},
+        path::{Component, Prefix},
+    };
+
+    let absolute = std::path::absolute(path)?;
+    let Some(Component::Prefix(prefix)) = absolute.components().next() else {
+        return Ok(absolute);
+    };
+
+    let wide: Vec<u16> = absolute.as_os_str().encode_wide().collect();
+    let extended: Vec<u16> = match prefix.kind() {
+        Prefix::Disk(_) => r"\\?\".encode_utf16().chain(wide).collect(),
+        // \\server\share becomes \\?\UNC\server\share
+        Prefix::UNC(..) => r"\\?\UNC\"
+            .encode_utf16()
+            .chain(wide.into_iter().skip(2))
+            .collect(),
+        // already verbatim, or a device path
+        _ => return Ok(absolute),
+    };
+
+    Ok(OsString::from_wide(&extended).into())
+}
+
+#[cfg(not(windows))]
+pub fn extended_length(path: &Path) -> std::io::Result<PathBuf> {
+    std::path::absolute(path)
+}
+
+/// How outputs are named after their notes, see --out-extension and --out-suffix
+#[derive(Clone)]
+pub struct OutputNaming {
+    pub extension: String,
+    pub suffix: Option<String>,
+    /// Place every output right in the output folder, see batch --flatten
+    pub flatten: bool,
+}
+
+impl OutputNaming {
+    /// The output in `output_folder` of the note at `relative_note` in the source
+    /// folder, mirroring its subfolders unless flattening
+    pub fn place(&self, output_folder: &Path, relative_note: &Path) -> PathBuf {
+        let relative_note = match relative_note.file_name() {
+            Some(name) if self.flatten => Path::new(name),
+            _ => relative_note,
+        };
+
+        self.output_of(&output_folder.join(relative_note))
+    }
+
+    /// `a/lecture.v2.rnote` becomes `a/lecture.v2.transcribed.md`, only the
+    /// note's own extension is replaced
+    pub fn output_of(&self, note: &Path) -> PathBuf {
+        let mut name = note.file_stem().map(OsString::from).unwrap_or_default();
+        if let Some(suffix) = &self.suffix {
+            name.push(".");
+            name.push(suffix);
+        }
+        name.push(".");
+        name.push(&self.extension);
+
+        note.with_file_name(name)
+    }
+
+    /// The `.rnote` note `output` was named after by [`OutputNaming::output_of`],
+    /// nothing for a file not named like an output. The `.gz` suffix of --gzip
+    /// outputs is ignored.
+    pub fn note_of(&self, output: &Path) -> Option<PathBuf> {
+        let name = output.file_name()?.to_str()?;
+        let name = name.strip_suffix(".gz").unwrap_or(name);
+        let mut stem = name
+            .strip_suffix(self.extension.as_str())?
+            .strip_suffix('.')?;
+        if let Some(suffix) = &self.suffix {
+            stem = stem.strip_suffix(suffix.as_str())?.strip_suffix('.')?;
+        }
+
+        (!stem.is_empty()).then(|| output.with_file_name(format!("{stem}.rnote")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(extension: &str, suffix: Option<&str>) -> OutputNaming {
+        OutputNaming {
+            extension: extension.to_owned(),
+            suffix: suffix.map(str::to_owned),
+            flatten: false,
+        }
+    }
+
+    #[test]
+    fn output_replaces_only_the_note_extension() {
+        let plain = naming("md", None);
+        let suffixed = naming("txt", Some("transcribed"));
+
+        assert_eq!(
+            plain.output_of(Path::new("a/lecture.v2.rnote")),
+            Path::new("a/lecture.v2.md")
+        );
+        assert_eq!(
+            suffixed.output_of(Path::new("a/lecture.v2.rnote")),
+            Path::new("a/lecture.v2.transcribed.txt")
+        );
+    }
+
+    #[test]
+    fn note_of_reverses_output_of() {
+        for naming in [naming("md", None), naming("txt", Some("transcribed"))] {
+            let note = Path::new("a/lecture.v2.rnote");
+
+            assert_eq!(
+                naming.note_of(&naming.output_of(note)).as_deref(),
+                Some(note)
+            );
+        }
+    }
+
+    #[test]
+    fn note_of_ignores_the_gzip_suffix() {
+        assert_eq!(
+            naming("md", None).note_of(Path::new("a/b.md.gz")),
+            Some(PathBuf::from("a/b.rnote"))
+        );
+    }
+
+    #[test]
+    fn note_of_rejects_files_not_named_like_outputs() {
+        let suffixed = naming("md", Some("transcribed"));
+
+        assert_eq!(naming("md", None).note_of(Path::new("a/b.txt")), None);
+        assert_eq!(naming("md", None).note_of(Path::new("a/bmd")), None);
+        assert_eq!(naming("md", None).note_of(Path::new("a/.md")), None);
+        assert_eq!(suffixed.note_of(Path::new("a/b.md")), None);
+        assert_eq!(suffixed.note_of(Path::new("a/.transcribed.md")), None);
+    }
+
+    #[test]
+    fn place_mirrors_subfolders_unless_flattening() {
+        let mut naming = naming("md", None);
+        let note = Path::new("course/week1/lecture.rnote");
+
+        assert_eq!(
+            naming.place(Path::new("out"), note),
+            Path::new("out/course/week1/lecture.md")
+        );
+
+        naming.flatten = true;
+        assert_eq!(
+            naming.place(Path::new("out"), note),
+            Path::new("out/lecture.md")
+        );
+    }
+}
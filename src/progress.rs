@@ -0,0 +1,87 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+/// Hooks into the lifecycle of every job of a run
+pub trait ProgressReporter {
+    fn started(&self, _input_file: &Path) {}
+
+    /// Called with the exported image as soon as it's ready, before the conversion
+    /// starts, e.g. to preview it while the model works.
+    fn exported(&self, _input_file: &Path, _image: &[u8], _mime: &str) {}
+
+    fn finished(&self, _input_file: &Path, _succeeded: bool) {}
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    total: usize,
+    done: usize,
+    current: Vec<PathBuf>,
+    failures: Vec<PathBuf>,
+}
+
+/// JSON snapshot of the overall progress, rewritten on every job transition for
+/// monitoring runs without a terminal.
+pub struct ProgressFile {
+    path: PathBuf,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl ProgressFile {
+    pub fn new(path: PathBuf, total: usize) -> Self {
+        let snapshot = Snapshot {
+            total,
+            done: 0,
+            current: vec![],
+            failures: vec![],
+        };
+
+        let progress = Self {
+            path,
+            snapshot: Mutex::new(snapshot),
+        };
+
+        progress.update(|_| {});
+        progress
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Snapshot)) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        change(&mut snapshot);
+
+        // progress reporting must never fail the run itself
+        if let Err(err) = self.write(&snapshot) {
+            log::warn!("writing progress file {}: {err}", self.path.display());
+        }
+    }
+
+    /// Write through a temporary file and rename it, so readers never see a truncated snapshot
+    fn write(&self, snapshot: &Snapshot) -> std::io::Result<()> {
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        std::fs::write(&temporary, serde_json::to_vec_pretty(snapshot)?)?;
+        std::fs::rename(&temporary, &self.path)
+    }
+}
+
+impl ProgressReporter for ProgressFile {
+    fn started(&self, input_file: &Path) {
+        self.update(|snapshot| snapshot.current.push(input_file.to_owned()));
+    }
+
+    fn finished(&self, input_file: &Path, succeeded: bool) {
+        self.update(|snapshot| {
+            snapshot.current.retain(|current| current != input_file);
+            snapshot.done += 1;
+
+            if !succeeded {
+                snapshot.failures.push(input_file.to_owned());
+            }
+        });
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{DirWalker, GENERATOR_MARKER, paths::OutputNaming, sink, stats};
+
+/// The notes of a batch, found from its outputs by reversing its mapping
+pub struct Sources {
+    source: PathBuf,
+    naming: OutputNaming,
+    extensions: Vec<String>,
+    /// Notes by file name, for a flattened batch that lost their folders
+    by_name: Option<HashMap<OsString, PathBuf>>,
+}
+
+impl Sources {
+    /// The notes in `source` a batch converted with `naming`, `extensions` and
+    /// `flatten`, as given to it. With `flatten` the outputs are matched to notes
+    /// by name alone, wherever they are in `source`.
+    pub fn new(
+        source: &Path,
+        naming: &OutputNaming,
+        extensions: &[String],
+        flatten: bool,
+    ) -> anyhow::Result<Self> {
+        let source = source.canonicalize()?;
+        let by_name = if flatten {
+            let mut by_name = HashMap::new();
+            for note in DirWalker::new(&source, usize::MAX)? {
+                if let Some(name) = note.file_name() {
+                    by_name.entry(name.to_owned()).or_insert(note);
+                }
+            }
+            Some(by_name)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            source,
+            naming: naming.clone(),
+            extensions: extensions.to_vec(),
+            by_name,
+        })
+    }
+
+    /// Whether `relative_output` is named like an output of the batch
+    pub fn is_output(&self, relative_output: &Path) -> bool {
+        self.naming.note_of(relative_output).is_some()
+    }
+
+    /// The existing note the output at `relative_output` in the destination folder
+    /// was converted from. Nothing for files not named like outputs, or whose note
+    /// is gone.
+    pub fn note_of(&self, relative_output: &Path) -> Option<PathBuf> {
+        let note = self.naming.note_of(relative_output)?;
+
+        self.extensions.iter().find_map(|extension| {
+            let mut name = note.file_stem().map(OsString::from).unwrap_or_default();
+            name.push(".");
+            name.push(extension.trim_start_matches('.'));
+
+            match &self.by_name {
+                Some(by_name) => by_name.get(&name).cloned(),
+                None => {
+                    Some(self.source.join(note.with_file_name(name))).filter(|note| note.is_file())
+                }
+            }
+        })
+    }
+}
+
+/// Outputs in `destination` whose note no longer exists among `sources`.
+///
+/// Only outputs carrying the `--annotate` marker are considered, so notes written
+/// by hand next to the generated ones are never deleted.
+pub fn orphans(sources: &Sources, destination: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let destination = destination.canonicalize()?;
+    let mut orphans = vec![];
+
+    for output_file in DirWalker::new(&destination, usize::MAX)? {
+        let relative_file = output_file.strip_prefix(&destination)?;
+        if !sources.is_output(relative_file) || sources.note_of(relative_file).is_some() {
+            continue;
+        }
+
+        let contents = std::fs::read(&output_file)
+            .and_then(|contents| sink::decode(&output_file, &contents))
+            .with_context(|| format!("reading {}", output_file.display()))?;
+        if !contents.contains(GENERATOR_MARKER) {
+            log::debug!(
+                "{} has no note but wasn't generated with --annotate, keeping it",
+                output_file.display()
+            );
+            continue;
+        }
+
+        orphans.push(output_file);
+    }
+
+    Ok(orphans)
+}
+
+/// Delete `orphans` along with their `--stats-sidecar` files, listing each of
+/// them. With `dry_run` they are only listed.
+pub fn remove(orphans: &[PathBuf], dry_run: bool) -> anyhow::Result<()> {
+    for output_file in orphans {
+        let sidecar = stats::sidecar(output_file);
+        let files = std::iter::once(output_file.as_path())
+            .chain(sidecar.is_file().then_some(sidecar.as_path()));
+
+        for file in files {
+            if dry_run {
+                println!("would remove {}", file.display());
+                continue;
+            }
+
+            std::fs::remove_file(file).with_context(|| format!("removing {}", file.display()))?;
+            println!("removed {}", file.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Paces the model requests of all jobs to at most a given number per minute,
+/// spreading them evenly instead of letting them start in bursts.
+///
+/// It only bounds when requests start, --concurrency still bounds how many are
+/// in flight.
+pub struct RateLimiter {
+    interval: Duration,
+    /// Earliest start of the next request
+    next_start: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / per_minute,
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Book the next free start for a request, which has to wait until then
+    pub fn reserve(&self) -> Instant {
+        let mut next_start = self.next_start.lock().unwrap();
+
+        let start = (*next_start).max(Instant::now());
+        *next_start = start + self.interval;
+
+        start
+    }
+}
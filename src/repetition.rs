@@ -0,0 +1,108 @@
+use std::fmt;
+
+/// Consecutive repeats of the same block of lines past which the output is
+/// considered stuck in a loop
+const MAX_REPEATS: usize = 20;
+
+/// Longest block of lines looked for repeating
+const MAX_BLOCK_LINES: usize = 4;
+
+/// Model output stuck repeating the same short block of lines
+#[derive(Debug)]
+pub struct Looping {
+    block: String,
+    repeats: usize,
+}
+
+impl fmt::Display for Looping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the model output repeats {:?} {} times in a row",
+            self.block, self.repeats
+        )
+    }
+}
+
+impl std::error::Error for Looping {}
+
+/// Look for a block of up to [`MAX_BLOCK_LINES`] lines repeated more than
+/// [`MAX_REPEATS`] times in a row. Blank lines are ignored, since loops often
+/// alternate them with the repeated content.
+pub fn find_loop(text: &str) -> Option<Looping> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    for block_lines in 1..=MAX_BLOCK_LINES {
+        let mut start = 0;
+
+        while start + block_lines <= lines.len() {
+            let block = &lines[start..start + block_lines];
+            let repeats = lines[start..]
+                .chunks_exact(block_lines)
+                .take_while(|chunk| *chunk == block)
+                .count();
+
+            if repeats > MAX_REPEATS {
+                return Some(Looping {
+                    block: block.join("\n"),
+                    repeats,
+                });
+            }
+
+            start += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repeated(block: &str, times: usize) -> String {
+        format!("{block}\n").repeat(times)
+    }
+
+    #[test]
+    fn varied_text_is_not_a_loop() {
+        assert!(find_loop("# Title\n\nSome notes.\n\n- a\n- b\n").is_none());
+        assert!(find_loop("").is_none());
+    }
+
+    #[test]
+    fn loop_needs_more_than_max_repeats() {
+        assert!(find_loop(&repeated("again", MAX_REPEATS)).is_none());
+
+        let looping = find_loop(&repeated("again", MAX_REPEATS + 1)).unwrap();
+        assert_eq!(looping.block, "again");
+        assert_eq!(looping.repeats, MAX_REPEATS + 1);
+    }
+
+    #[test]
+    fn multi_line_blocks_are_found() {
+        let text = format!("# Notes\n{}", repeated("| a |\n| b |", 30));
+        let looping = find_loop(&text).unwrap();
+
+        assert_eq!(looping.block, "| a |\n| b |");
+        assert_eq!(looping.repeats, 30);
+    }
+
+    #[test]
+    fn blocks_longer_than_max_block_lines_are_ignored() {
+        let block: Vec<String> = (0..=MAX_BLOCK_LINES).map(|i| format!("line {i}")).collect();
+
+        assert!(find_loop(&repeated(&block.join("\n"), 30)).is_none());
+    }
+
+    #[test]
+    fn blank_lines_and_trailing_spaces_are_ignored() {
+        let text = "same  \n\n\nsame\n".repeat(MAX_REPEATS);
+
+        assert_eq!(find_loop(&text).unwrap().repeats, 2 * MAX_REPEATS);
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::failures::Category;
+
+/// How every job of a run ended, summarized at its end and written as JSON with
+/// `--report`
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub converted: usize,
+    pub skipped: usize,
+    /// Jobs skipped because --max-requests ran out
+    pub over_budget: usize,
+    pub failed: Vec<FailedJob>,
+}
+
+#[derive(Serialize)]
+pub struct FailedJob {
+    pub input_file: PathBuf,
+    pub output_file: PathBuf,
+    pub category: Category,
+    pub reason: String,
+}
+
+impl Report {
+    pub fn fail(&mut self, input_file: PathBuf, output_file: PathBuf, err: &anyhow::Error) {
+        self.failed.push(FailedJob {
+            input_file,
+            output_file,
+            category: Category::of(err),
+            reason: format!("{err:#}"),
+        });
+    }
+
+    /// Number of failed jobs in each category
+    pub fn categories(&self) -> BTreeMap<Category, usize> {
+        let mut categories = BTreeMap::new();
+        for job in &self.failed {
+            *categories.entry(job.category).or_default() += 1;
+        }
+        categories
+    }
+
+    /// The `(input, output)` pairs of the failed jobs, for the failure log
+    pub fn failed_pairs(&self) -> Vec<(PathBuf, PathBuf)> {
+        self.failed
+            .iter()
+            .map(|job| (job.input_file.clone(), job.output_file.clone()))
+            .collect()
+    }
+
+    pub fn log_summary(&self) {
+        log::info!(
+            "{} converted, {} skipped, {} failed",
+            self.converted,
+            self.skipped + self.over_budget,
+            self.failed.len()
+        );
+
+        for job in &self.failed {
+            log::warn!("failed: {}", job.input_file.display());
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing report {}", path.display()))
+    }
+}
@@ -0,0 +1,194 @@
+use std::{fmt, time::Duration};
+
+use crate::backend::BadStatus;
+
+/// Attempts made for a model request before giving up on it
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Exports fail for different reasons than requests, like resource contention
+/// inside the engine, so they get their own, shorter, schedule.
+pub const EXPORT_BACKOFF: Backoff = Backoff {
+    base: Duration::from_millis(250),
+    max: Duration::from_secs(2),
+    attempts: 3,
+};
+
+/// Exponential backoff between retries of a failed operation
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    pub attempts: u32,
+}
+
+impl Backoff {
+    /// Delay before retrying after the `attempt`-th failure. Half of it is random,
+    /// so jobs failing together, e.g. on a rate limit, don't all retry together.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max);
+
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    /// Run `request` until it succeeds, fails with an error `is_retryable` rejects or
+    /// runs out of attempts. `on_retry` is told about every retry before waiting for it.
+    pub async fn retry<T, F>(
+        &self,
+        mut request: impl FnMut() -> F,
+        is_retryable: impl Fn(&anyhow::Error) -> bool,
+        mut on_retry: impl FnMut(u32, Duration, &anyhow::Error),
+    ) -> anyhow::Result<T>
+    where
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let err = match request().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if attempt >= self.attempts || !is_retryable(&err) {
+                return Err(err);
+            }
+
+            // the server knows best how long its rate limit lasts
+            let delay = server_retry_delay(&err).unwrap_or_else(|| self.delay(attempt));
+            on_retry(attempt, delay, &err);
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// A model request that got no answer within --timeout-secs
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request timed out after {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Rate limits, server errors, timeouts and network failures are worth retrying,
+/// while anything else (bad key, bad request) would just fail again.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    if err.is::<TimedOut>() {
+        return true;
+    }
+    if let Some(BadStatus { code, .. }) = err.downcast_ref::<BadStatus>() {
+        return *code == 429 || (500..600).contains(code);
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_connect() || err.is_timeout() || err.is_request();
+    }
+
+    match err.downcast_ref::<gemini_rust::ClientError>() {
+        Some(gemini_rust::ClientError::BadResponse { code, .. }) => {
+            *code == 429 || (500..600).contains(code)
+        }
+        Some(gemini_rust::ClientError::PerformRequest { .. }) => true,
+        _ => false,
+    }
+}
+
+/// A rejected key or missing permission, which no other model gets past either
+pub fn is_auth_error(err: &anyhow::Error) -> bool {
+    if let Some(BadStatus { code, .. }) = err.downcast_ref::<BadStatus>() {
+        return matches!(code, 401 | 403);
+    }
+
+    matches!(
+        err.downcast_ref::<gemini_rust::ClientError>(),
+        Some(gemini_rust::ClientError::BadResponse {
+            code: 401 | 403,
+            ..
+        })
+    )
+}
+
+/// The `retryDelay` Gemini includes in the body of its 429 responses, since the
+/// client doesn't expose the `Retry-After` header itself.
+fn server_retry_delay(err: &anyhow::Error) -> Option<Duration> {
+    let Some(gemini_rust::ClientError::BadResponse {
+        description: Some(description),
+        ..
+    }) = err.downcast_ref::<gemini_rust::ClientError>()
+    else {
+        return None;
+    };
+
+    parse_retry_delay(description)
+}
+
+/// `"retryDelay": "12.5s"` anywhere in `description`
+fn parse_retry_delay(description: &str) -> Option<Duration> {
+    let (_, rest) = description.split_once("\"retryDelay\"")?;
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let (seconds, _) = rest.strip_prefix('"')?.split_once('s')?;
+
+    Duration::try_from_secs_f64(seconds.parse().ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_is_read_from_the_error_body() {
+        let body = r#"{"error": {"code": 429, "details": [{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "17s"}]}}"#;
+
+        assert_eq!(parse_retry_delay(body), Some(Duration::from_secs(17)));
+    }
+
+    #[test]
+    fn retry_delay_accepts_fractions_and_spacing() {
+        assert_eq!(
+            parse_retry_delay(r#""retryDelay" :  "1.5s""#),
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn malformed_retry_delay_is_ignored() {
+        for body in [
+            "",
+            "quota exceeded",
+            r#""retryDelay": 17"#,
+            r#""retryDelay": "soon""#,
+            r#""retryDelay": "-3s""#,
+            r#""retryDelay": "17""#,
+        ] {
+            assert_eq!(parse_retry_delay(body), None, "{body}");
+        }
+    }
+
+    #[test]
+    fn non_gemini_errors_carry_no_delay() {
+        let err = anyhow::Error::new(TimedOut(Duration::from_secs(1)));
+
+        assert_eq!(server_retry_delay(&err), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_its_max_with_jitter() {
+        let backoff = Backoff {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+            attempts: 10,
+        };
+
+        for (attempt, full) in [(1, 1), (2, 2), (3, 4), (4, 5), (9, 5)] {
+            let full = Duration::from_secs(full);
+            let delay = backoff.delay(attempt);
+            assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+        }
+    }
+}
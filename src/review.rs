@@ -0,0 +1,112 @@
+use std::io::{BufRead, Write};
+
+use clap::ValueEnum;
+
+use crate::cli::{Model, Prompt};
+
+/// What to do with a conversion shown with `--interactive`
+pub enum Decision {
+    Accept,
+    /// Skip writing the output
+    Reject,
+    RetryWithPrompt(Prompt),
+    RetryWithModel(Model),
+}
+
+/// Show `markdown` and ask what to do with it until a valid answer comes in. The
+/// end of the input rejects the conversion, leaving the output untouched.
+pub fn ask(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    name: &str,
+    markdown: &str,
+) -> std::io::Result<Decision> {
+    writeln!(output, "===== {name} =====\n{markdown}\n==========")?;
+
+    loop {
+        write!(
+            output,
+            "[a]ccept, [r]eject, retry with [p]rompt <name> or [m]odel <name>: "
+        )?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(Decision::Reject);
+        }
+
+        match parse(answer.trim()) {
+            Ok(decision) => return Ok(decision),
+            Err(err) => writeln!(output, "{err}")?,
+        }
+    }
+}
+
+fn parse(answer: &str) -> Result<Decision, String> {
+    let (command, argument) = answer.split_once(' ').unwrap_or((answer, ""));
+    let argument = argument.trim();
+
+    match command {
+        "a" | "accept" => Ok(Decision::Accept),
+        "r" | "reject" => Ok(Decision::Reject),
+        "p" | "prompt" => Prompt::from_str(argument, true).map(Decision::RetryWithPrompt),
+        "m" | "model" => Model::from_str(argument, true).map(Decision::RetryWithModel),
+        _ => Err(format!("unknown answer {answer:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer with `script`, returning the decision and everything shown
+    fn drive(script: &str) -> (Decision, String) {
+        let mut output = vec![];
+        let decision = ask(script.as_bytes(), &mut output, "note.rnote", "# Notes").unwrap();
+
+        (decision, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn shows_the_conversion_first() {
+        let (_, shown) = drive("a\n");
+
+        assert!(shown.starts_with("===== note.rnote =====\n# Notes\n==========\n"));
+    }
+
+    #[test]
+    fn accepts_and_rejects() {
+        assert!(matches!(drive("a\n").0, Decision::Accept));
+        assert!(matches!(drive("accept\n").0, Decision::Accept));
+        assert!(matches!(drive("r\n").0, Decision::Reject));
+        assert!(matches!(drive("  reject  \n").0, Decision::Reject));
+    }
+
+    #[test]
+    fn retries_with_another_model_or_prompt() {
+        assert!(matches!(
+            drive("m gemini25-pro\n").0,
+            Decision::RetryWithModel(Model::Gemini25Pro)
+        ));
+        assert!(matches!(
+            drive("prompt math\n").0,
+            Decision::RetryWithPrompt(Prompt::Math)
+        ));
+    }
+
+    #[test]
+    fn asks_again_after_an_invalid_answer() {
+        let (decision, shown) = drive("maybe\nm gpt\na\n");
+
+        assert!(matches!(decision, Decision::Accept));
+        assert!(shown.contains("unknown answer \"maybe\""));
+        // the model name is rejected by clap, and the question comes back each time
+        assert_eq!(shown.matches("[a]ccept").count(), 3);
+    }
+
+    #[test]
+    fn end_of_input_rejects() {
+        assert!(matches!(drive("").0, Decision::Reject));
+        assert!(matches!(drive("what\n").0, Decision::Reject));
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, convert::Infallible, rc::Rc, sync::Arc};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::{CONTENT_TYPE, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{net::TcpListener, task::LocalSet};
+
+use crate::{
+    backend, check_markdown,
+    cli::{Model, Options, Prompt},
+    convert_note, export_with_retries, process_images,
+};
+
+/// What the note of a request is called in errors and logs
+const REQUEST_NAME: &str = "request body";
+
+/// Images accepted as is instead of an `.rnote` body
+const IMAGE_MIMES: [&str; 3] = ["image/png", "image/jpeg", "image/webp"];
+
+/// Local HTTP API converting single notes, for editor integrations.
+///
+/// `POST /convert` takes the bytes of an `.rnote` file, or an image with a matching
+/// `Content-Type`, and answers with the markdown. The `prompt` and `model` query
+/// parameters pick a built-in prompt and a model for that request alone.
+pub struct Server {
+    options: Options,
+    system_prompt: String,
+    prompt_context: String,
+}
+
+impl Server {
+    pub fn new(options: Options, system_prompt: String, prompt_context: String) -> Self {
+        Self {
+            options,
+            system_prompt,
+            prompt_context,
+        }
+    }
+
+    pub async fn serve(self, port: u16) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .context("binding server port")?;
+        log::info!("listening on http://{}", listener.local_addr()?);
+
+        // the engine isn't Send, so connections are served on this thread alone
+        LocalSet::new()
+            .run_until(accept_connections(listener, Rc::new(self)))
+            .await
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        if request.uri().path() != "/convert" {
+            return respond(StatusCode::NOT_FOUND, "not found".into());
+        }
+        if request.method() != Method::POST {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "use POST".into());
+        }
+
+        match self.convert(request).await {
+            Ok(markdown) => {
+                let mut response = respond(StatusCode::OK, markdown);
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/markdown; charset=utf-8"),
+                );
+                response
+            }
+            Err(err) => {
+                log::warn!("conversion request failed: {err:#}");
+                respond(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+            }
+        }
+    }
+
+    async fn convert(&self, request: Request<Incoming>) -> anyhow::Result<String> {
+        let params: HashMap<String, String> = request
+            .uri()
+            .query()
+            .map(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let body = request
+            .into_body()
+            .collect()
+            .await
+            .context("reading request body")?
+            .to_bytes()
+            .to_vec();
+
+        // exported off this thread, which all the connections share
+        let (images, mime) = match IMAGE_MIMES
+            .into_iter()
+            .find(|mime| content_type.as_deref() == Some(*mime))
+        {
+            Some(mime) => (vec![body], mime),
+            None => {
+                let note: Arc<[u8]> = body.into();
+                let read = move || anyhow::Ok(note.to_vec());
+                let (export, _, _) = export_with_retries(
+                    REQUEST_NAME,
+                    read,
+                    &self.options,
+                    &self.options.export_settings(),
+                )
+                .await?;
+                export
+            }
+        };
+        let (images, mime) = process_images(images, mime, &self.options).await?;
+        let mime = self.options.image_mime.as_deref().unwrap_or(mime);
+
+        let mut system_prompt = match params.get("prompt") {
+            Some(name) => Prompt::from_str(name, true)
+                .map_err(anyhow::Error::msg)?
+                .text()
+                .to_owned(),
+            None => self.system_prompt.clone(),
+        };
+        system_prompt.push_str(&self.prompt_context);
+
+        let (model, thinking) = match params.get("model") {
+            Some(name) => {
+                let model = Model::from_str(name, true).map_err(anyhow::Error::msg)?;
+                let flash = matches!(model, Model::Gemini25Flash);
+                (Some(model), self.options.thinking_budget_for(flash))
+            }
+            None => (None, self.options.thinking_budget()),
+        };
+        let client = backend::converter(&self.options, model)?;
+
+        let markdown = convert_note(
+            client.as_ref(),
+            system_prompt,
+            &images,
+            mime,
+            thinking,
+            &self.options,
+        )
+        .await?;
+        let (markdown, _) = check_markdown(markdown, REQUEST_NAME, &self.options);
+
+        Ok(self.options.final_newline.apply(markdown))
+    }
+}
+
+async fn accept_connections(listener: TcpListener, server: Rc<Server>) -> anyhow::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await.context("accepting connection")?;
+        let server = server.clone();
+
+        tokio::task::spawn_local(async move {
+            let service = service_fn(|request| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(server.handle(request).await) }
+            });
+
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::warn!("serving connection failed: {err}");
+            }
+        });
+    }
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,115 @@
+use std::path::{Component, Path};
+
+use crate::stable_hash::fnv1a;
+
+/// One of `total` disjoint slices of the jobs, for splitting a batch across hosts
+/// without coordinating them
+#[derive(Clone, Copy)]
+pub struct Shard {
+    pub index: u64,
+    pub total: u64,
+}
+
+impl Shard {
+    /// Keep the items whose `key` path hashes into this shard.
+    ///
+    /// Paths are hashed relative to the deepest folder all of them share, so
+    /// hosts with the vault mounted in different places still agree on the split.
+    pub fn select<T>(&self, items: Vec<T>, key: impl Fn(&T) -> &Path) -> Vec<T> {
+        let common = items
+            .iter()
+            .map(|item| key(item).parent().unwrap_or(Path::new("")))
+            .reduce(|common, path| common_ancestor(common, path))
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        items
+            .into_iter()
+            .filter(|item| {
+                let relative = key(item).strip_prefix(&common).unwrap_or(key(item));
+                stable_hash(relative) % self.total == self.index
+            })
+            .collect()
+    }
+}
+
+fn common_ancestor<'a>(a: &'a Path, b: &Path) -> &'a Path {
+    a.ancestors()
+        .find(|ancestor| b.starts_with(ancestor))
+        .unwrap_or(Path::new(""))
+}
+
+/// Hash of the `/` joined components, the same whichever platform splits the paths
+fn stable_hash(path: &Path) -> u64 {
+    let normalized = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    fnv1a(normalized.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn notes(root: &str) -> Vec<PathBuf> {
+        (0..50)
+            .map(|i| Path::new(root).join(format!("course{}/lecture{i}.rnote", i % 4)))
+            .collect()
+    }
+
+    #[test]
+    fn shards_partition_the_items() {
+        let items = notes("/vault");
+        let total = 3;
+
+        let mut union: Vec<PathBuf> = (0..total)
+            .flat_map(|index| Shard { index, total }.select(items.clone(), |item| item))
+            .collect();
+        union.sort();
+        let mut expected = items.clone();
+        expected.sort();
+
+        // every item lands in exactly one shard
+        assert_eq!(union, expected);
+    }
+
+    #[test]
+    fn single_shard_keeps_everything() {
+        let items = notes("/vault");
+        let selected = Shard { index: 0, total: 1 }.select(items.clone(), |item| item);
+
+        assert_eq!(selected, items);
+    }
+
+    #[test]
+    fn split_ignores_where_the_vault_is_mounted() {
+        let shard = Shard { index: 1, total: 4 };
+        let names = |root: &str| -> Vec<PathBuf> {
+            shard
+                .select(notes(root), |item| item)
+                .iter()
+                .map(|item| item.strip_prefix(root).unwrap().to_path_buf())
+                .collect()
+        };
+
+        assert_eq!(names("/vault"), names("/mnt/backup/vault"));
+    }
+
+    #[test]
+    fn hash_is_fnv1a_of_the_joined_components() {
+        assert_eq!(stable_hash(Path::new("")), 0xcbf29ce484222325);
+        assert_eq!(stable_hash(Path::new("a")), 0xaf63dc4c8601ec8c);
+        assert_eq!(
+            stable_hash(Path::new("a/b.rnote")),
+            stable_hash(Path::new("./a//b.rnote"))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fmt,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use tokio::io::AsyncWriteExt;
+
+/// Destination of the converted notes
+pub trait OutputSink {
+    /// Store `content` as the output of the job writing to `output_file`. Sinks not
+    /// backed by the filesystem can treat the path as a key.
+    fn write(
+        &self,
+        output_file: &Path,
+        content: String,
+    ) -> impl Future<Output = anyhow::Result<()>>;
+}
+
+/// Output path standing for the standard output, like `single note.rnote -`
+pub const STDOUT: &str = "-";
+
+/// Writes every output to its path on the local filesystem, or to the standard
+/// output for [`STDOUT`]
+pub struct FileSink {
+    /// Gzip compress the outputs, whose paths are expected to carry the `.gz` suffix
+    pub gzip: bool,
+    /// Unix permissions of the folders created for the outputs, instead of the umask's
+    pub dir_mode: Option<u32>,
+    /// Unix permissions of the outputs, instead of the umask's
+    pub file_mode: Option<u32>,
+}
+
+impl OutputSink for FileSink {
+    async fn write(&self, output_file: &Path, content: String) -> anyhow::Result<()> {
+        if output_file == Path::new(STDOUT) {
+            let content = if self.gzip {
+                gzip(content.as_bytes())?
+            } else {
+                content.into_bytes()
+            };
+
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&content).await.map_err(write_error)?;
+            stdout.flush().await.map_err(write_error)?;
+            return Ok(());
+        }
+
+        if let Some(parent) = output_file.parent() {
+            // only the folders created here get the mode, not the existing parents
+            let missing: Vec<PathBuf> = parent
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .map(Path::to_path_buf)
+                .collect();
+
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(write_error)?;
+
+            if let Some(mode) = self.dir_mode {
+                for dir in missing {
+                    set_mode(&dir, mode).await?;
+                }
+            }
+        }
+
+        let content = if self.gzip {
+            gzip(content.as_bytes())?
+        } else {
+            content.into_bytes()
+        };
+
+        write_atomically(output_file, content, self.file_mode)
+            .await
+            .map_err(write_error)
+    }
+}
+
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    tokio::fs::set_permissions(path, Permissions::from_mode(mode)).await
+}
+
+#[cfg(not(unix))]
+async fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn gzip(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+/// Text of the existing output `output_file` read as `contents`, decompressing
+/// the `.gz` ones [`FileSink`] wrote with `--gzip`
+pub fn decode(output_file: &Path, contents: &[u8]) -> std::io::Result<String> {
+    if output_file.extension().is_none_or(|ext| ext != "gz") {
+        return Ok(String::from_utf8_lossy(contents).into_owned());
+    }
+
+    let mut decoded = Vec::new();
+    GzDecoder::new(contents).read_to_end(&mut decoded)?;
+
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Context of write errors caused by a full disk, which every following job would hit too
+#[derive(Debug)]
+pub struct DiskFull;
+
+impl fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the destination disk is full")
+    }
+}
+
+pub fn write_error(err: std::io::Error) -> anyhow::Error {
+    if err.kind() == std::io::ErrorKind::StorageFull {
+        anyhow::Error::new(err).context(DiskFull)
+    } else {
+        err.into()
+    }
+}
+
+/// Write through a temporary file renamed over `path`, so an interrupted run never
+/// leaves a truncated output behind
+async fn write_atomically(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    tokio::fs::write(&temporary, contents).await?;
+    if let Some(mode) = mode {
+        set_mode(Path::new(&temporary), mode).await?;
+    }
+    tokio::fs::rename(&temporary, path).await
+}
@@ -0,0 +1,59 @@
+use sha2::{Digest, Sha256};
+
+/// FNV-1a, identical on every platform and toolchain unlike the std hashers. Good
+/// enough to spread items evenly, too short to tell contents apart: see
+/// [`KeyHasher`] for that.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// SHA-256 over the fields of a cache key. Entries are looked up by their key
+/// alone, so a collision would silently hand back another note's output, which a
+/// 64-bit hash can't rule out.
+pub struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    /// Hash `bytes` as one field of the key, length prefixed so neighbouring fields
+    /// can't run into each other
+    pub fn field(&mut self, bytes: impl AsRef<[u8]>) -> &mut Self {
+        let bytes = bytes.as_ref();
+        self.0.update((bytes.len() as u64).to_le_bytes());
+        self.0.update(bytes);
+        self
+    }
+
+    /// [`KeyHasher::field`] for a field that may be left out, which hashes apart
+    /// from any value
+    pub fn optional_field(&mut self, bytes: Option<impl AsRef<[u8]>>) -> &mut Self {
+        match bytes {
+            Some(bytes) => {
+                self.0.update([1]);
+                self.field(bytes)
+            }
+            None => {
+                self.0.update([0]);
+                self
+            }
+        }
+    }
+
+    /// The key, in hex
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use rnote_engine::{Engine, strokes::Stroke};
+use serde::Serialize;
+
+/// Basic statistics about the exported strokes of a note, written next to its
+/// markdown with `--stats-sidecar`
+#[derive(Serialize)]
+pub struct NoteStats {
+    strokes: usize,
+    /// Summed length of the pen paths, in document units
+    total_length: f64,
+    /// `[min_x, min_y, max_x, max_y]` of the exported strokes
+    bounds: Option<[f64; 4]>,
+    /// Number of strokes drawn in each `#rrggbbaa` color
+    colors: BTreeMap<String, usize>,
+    pub timings: Timings,
+}
+
+/// Milliseconds spent in each stage of a job. Stages that ran more than once,
+/// like extra passes or an --adaptive-dpi retry, are summed.
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Timings {
+    /// Reading the note and loading it into the engine
+    pub load_ms: u64,
+    /// Rendering the image, including the WebP encoding
+    pub export_ms: u64,
+    /// Model requests, including the delays between retries
+    pub api_ms: u64,
+    /// Writing the markdown output
+    pub write_ms: u64,
+}
+
+impl Timings {
+    pub fn since(start: Instant) -> u64 {
+        start.elapsed().as_millis() as u64
+    }
+}
+
+impl NoteStats {
+    /// Gather the statistics of the strokes currently selected for export
+    pub fn of_selection(engine: &Engine) -> Self {
+        let keys = engine.store.selection_keys_unordered();
+
+        let mut total_length = 0.0;
+        let mut colors = BTreeMap::new();
+
+        for stroke in keys
+            .iter()
+            .filter_map(|&key| engine.store.get_stroke_ref(key))
+        {
+            let Stroke::BrushStroke(brushstroke) = stroke else {
+                continue;
+            };
+
+            // chords between the path elements, which is plenty for handwriting
+            let path = &brushstroke.path;
+            let mut previous = path.start.pos;
+            for segment in &path.segments {
+                let end = segment.end().pos;
+                total_length += (end - previous).norm();
+                previous = end;
+            }
+
+            if let Some(color) = brushstroke.style.stroke_color() {
+                let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let hex = format!(
+                    "#{:02x}{:02x}{:02x}{:02x}",
+                    channel(color.r),
+                    channel(color.g),
+                    channel(color.b),
+                    channel(color.a)
+                );
+                *colors.entry(hex).or_default() += 1;
+            }
+        }
+
+        let bounds = engine.store.bounds_for_strokes(&keys).map(|bounds| {
+            [
+                bounds.mins[0],
+                bounds.mins[1],
+                bounds.maxs[0],
+                bounds.maxs[1],
+            ]
+        });
+
+        Self {
+            strokes: keys.len(),
+            total_length,
+            bounds,
+            colors,
+            timings: Timings::default(),
+        }
+    }
+}
+
+/// `<name>.stats.json` next to the `<name>.md` (or `<name>.md.gz`) output
+pub fn sidecar(output_file: &Path) -> PathBuf {
+    let mut sidecar = output_file.to_path_buf();
+    if sidecar.extension().is_some_and(|ext| ext == "gz") {
+        sidecar.set_extension("");
+    }
+    sidecar.set_extension("stats.json");
+    sidecar
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// What --output-json writes for every note, in the shape the model is told to answer in
+#[derive(Serialize, Deserialize)]
+pub struct NoteJson {
+    pub title: String,
+    pub text: String,
+    pub headings: Vec<String>,
+    pub confidence: String,
+}
+
+/// Response schema constraining the model to a [`NoteJson`]. Plain JSON Schema,
+/// which both Gemini and OpenAI-compatible endpoints accept.
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "title": {
+                "type": "string",
+                "description": "Title of the note, as written on it or inferred from its content",
+            },
+            "text": {
+                "type": "string",
+                "description": "Full transcription of the note, as markdown",
+            },
+            "headings": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Headings of the transcription, in order",
+            },
+            "confidence": {
+                "type": "string",
+                "description": "Short note on how legible the handwriting was and what may be misread",
+            },
+        },
+        "required": ["title", "text", "headings", "confidence"],
+    })
+}
+
+/// The model's `answer` checked against [`NoteJson`], pretty printed for writing
+pub fn parse(answer: &str) -> Result<String, InvalidJson> {
+    let note: NoteJson = serde_json::from_str(answer.trim()).map_err(InvalidJson)?;
+
+    Ok(serde_json::to_string_pretty(&note).expect("serializing to a string can't fail"))
+}
+
+/// An answer not following [`schema`] despite being asked to
+#[derive(Debug)]
+pub struct InvalidJson(serde_json::Error);
+
+impl fmt::Display for InvalidJson {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the model's answer doesn't match the JSON schema: {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidJson {}
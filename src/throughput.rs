@@ -0,0 +1,44 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Completions the rate is averaged over, few enough to follow the batch moving
+/// between small and big notes
+const WINDOW: usize = 10;
+
+/// Rolling rate of completed jobs
+pub struct Throughput {
+    completions: VecDeque<Instant>,
+}
+
+impl Throughput {
+    pub fn new(start: Instant) -> Self {
+        let mut completions = VecDeque::with_capacity(WINDOW + 1);
+        completions.push_back(start);
+
+        Self { completions }
+    }
+
+    pub fn record(&mut self, completed: Instant) {
+        if self.completions.len() > WINDOW {
+            self.completions.pop_front();
+        }
+        self.completions.push_back(completed);
+    }
+
+    /// Jobs completed per minute over the window, once there is one to measure
+    pub fn per_minute(&self) -> Option<f64> {
+        let (first, last) = (self.completions.front()?, self.completions.back()?);
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        let completed = self.completions.len() - 1;
+
+        (completed > 0 && elapsed > 0.0).then(|| completed as f64 * 60.0 / elapsed)
+    }
+
+    /// Time left for `remaining` jobs at the current rate
+    pub fn eta(&self, remaining: usize) -> Option<Duration> {
+        let per_minute = self.per_minute()?;
+        Duration::try_from_secs_f64(remaining as f64 * 60.0 / per_minute).ok()
+    }
+}
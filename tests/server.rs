@@ -0,0 +1,196 @@
+//! Drives `rnote-llm serve` over HTTP, with a fake OpenAI-compatible endpoint
+//! standing in for the model
+#![cfg(feature = "server")]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+
+/// Read an HTTP/1.1 request off `stream`, returning its request line and body
+fn read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    (request_line.trim_end().to_owned(), body)
+}
+
+/// Endpoint answering every completion with `markdown`, returning its base url
+/// and the request bodies it received
+fn fake_model(markdown: &'static str) -> (String, Receiver<serde_json::Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}/v1/", listener.local_addr().unwrap());
+    let (requests, received) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let (_, body) = read_request(&mut stream);
+            let _ = requests.send(serde_json::from_slice(&body).unwrap());
+
+            let answer = serde_json::json!({ "choices": [{ "message": { "content": markdown } }] })
+                .to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{answer}",
+                answer.len()
+            )
+            .unwrap();
+        }
+    });
+
+    (base_url, received)
+}
+
+/// `rnote-llm serve` talking to `base_url`, killed when dropped
+struct Served {
+    child: Child,
+    port: u16,
+}
+
+impl Served {
+    fn start(base_url: &str, extra_args: &[&str]) -> Self {
+        // the port is free again once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_rnote-llm"))
+            .args(["--backend", "openai", "--base-url", base_url])
+            .args(["--model-name", "fake"])
+            .args(extra_args)
+            .args(["serve", "--port", &port.to_string()])
+            // away from any rnote-llm.toml
+            .current_dir(std::env::temp_dir())
+            .env_remove("GEMINI_API_KEY")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                Instant::now() < deadline,
+                "the server never started listening"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Self { child, port }
+    }
+
+    /// Send a request, returning the status code and body of the answer
+    fn request(
+        &self,
+        method: &str,
+        target: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        write!(
+            stream,
+            "{method} {target} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+        (status, body.to_owned())
+    }
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn converts_posted_images() {
+    let (base_url, received) = fake_model("# Lecture");
+    let served = Served::start(&base_url, &[]);
+    let image = b"pretend this is a png";
+
+    let (status, markdown) = served.request("POST", "/convert", "image/png", image);
+
+    assert_eq!(status, 200);
+    assert_eq!(markdown, "# Lecture\n");
+
+    let request = received.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(request["model"], "fake");
+    assert_eq!(
+        request["messages"][1]["content"][0]["image_url"]["url"],
+        format!("data:image/png;base64,{}", BASE64_STANDARD.encode(image))
+    );
+}
+
+#[test]
+fn repairs_the_markdown_with_fix_md() {
+    let (base_url, _received) = fake_model("```\ncode");
+    let served = Served::start(&base_url, &["--fix-md"]);
+
+    let (status, markdown) = served.request("POST", "/convert", "image/png", b"image");
+
+    assert_eq!(status, 200);
+    assert_eq!(markdown, "```\ncode\n```\n");
+}
+
+#[test]
+fn rejects_bodies_that_are_not_notes() {
+    let (base_url, received) = fake_model("# Lecture");
+    let served = Served::start(&base_url, &[]);
+
+    let (status, _) = served.request(
+        "POST",
+        "/convert",
+        "application/octet-stream",
+        b"plain text",
+    );
+
+    assert_eq!(status, 500);
+    // nothing worth sending to the model
+    assert!(received.try_recv().is_err());
+}
+
+#[test]
+fn answers_only_posts_to_convert() {
+    let (base_url, _received) = fake_model("# Lecture");
+    let served = Served::start(&base_url, &[]);
+
+    assert_eq!(served.request("GET", "/convert", "image/png", b"").0, 405);
+    assert_eq!(served.request("POST", "/other", "image/png", b"").0, 404);
+}